@@ -0,0 +1,110 @@
+// Windowing backends
+//
+// Everything that depends on the display server lives behind the Backend
+// trait: creating the bars, keeping them in place when outputs come and go,
+// and getting finished frames on the screen. The event loop here only knows
+// about file descriptors, so inputs and drawing don't care where they end up.
+
+extern crate mio;
+
+use surface::Surface;
+
+use self::mio::*;
+
+use std::boxed::Box;
+use std::collections::HashMap;
+use std::os::raw::c_int;
+
+/// The part of the wallpaper behind a bar
+pub struct RootImage {
+    /* The bar this was taken for */
+    pub bar: u32,
+    /* Changes every time the images are grabbed again, and is the same for
+     * all bars grabbed at once */
+    pub serial: u32,
+    pub width: u32,
+    pub height: u32,
+    /* RGBA, 8 bits per channel */
+    pub data: Vec<u8>,
+}
+
+/// What a single bar needs drawn
+pub struct Frame<'a> {
+    pub width: u32,
+    pub height: u32,
+    /* HiDPI scale of the monitor the bar is on */
+    pub scale: f64,
+    /* Only backends that can see the wallpaper provide this */
+    pub root: Option<&'a RootImage>,
+}
+
+pub trait Backend {
+    type Surface: Surface;
+
+    /// File descriptors that become readable when the display server has
+    /// something for the backend
+    fn get_fds(&self) -> Vec<c_int>;
+
+    /// Handle everything the display server sent so far. This is where bars
+    /// get created, moved and removed when outputs change.
+    /// Returns true if the bars need to be redrawn
+    fn handle_events(&mut self) -> bool;
+
+    /// False once the bar should shut down, e.g. because the window got closed
+    fn is_running(&self) -> bool;
+
+    /// Draw a frame for every bar and put it on the screen
+    fn present<F>(&mut self, draw: &mut F)
+        where F: FnMut(&mut Self::Surface, &Frame);
+}
+
+/// Run the bar until the backend stops.
+/// updates are the input fds with the function that reads them, which return
+/// true if the bar content changed. draw is called for every bar, drawn once
+/// after all of them
+pub fn run<B, F, D, G>(backend: &mut B, updates: G, mut draw: F, mut drawn: D)
+    where B: Backend,
+          F: FnMut(&mut B::Surface, &Frame),
+          D: FnMut(),
+          G: IntoIterator<Item=(c_int, Box<FnMut() -> bool>)> {
+    let poll = Poll::new().unwrap();
+
+    /* The backend doesn't need a handler, it's asked after every wakeup */
+    for fd in backend.get_fds() {
+        poll.register(&mio::unix::EventedFd(&fd), Token(fd as usize),
+                      Ready::readable(), PollOpt::level()).unwrap();
+    }
+
+    let mut map = HashMap::new();
+    for x in updates {
+        let tok = Token(x.0 as usize);
+        poll.register(&mio::unix::EventedFd(&x.0), tok, Ready::readable(),
+                      PollOpt::level()).unwrap();
+        map.insert(tok, x.1);
+    }
+
+    let mut events = Events::with_capacity(map.len() + 2);
+    let mut redraw = false;
+    loop {
+        /* Display servers may queue events without the fd becoming readable,
+         * so this has to be done before every poll */
+        redraw |= backend.handle_events();
+        if !backend.is_running() {
+            break;
+        }
+
+        if redraw {
+            /* All bars show the same inputs, so they all need a redraw */
+            backend.present(&mut draw);
+            drawn();
+            redraw = false;
+        }
+
+        poll.poll(&mut events, None).unwrap();
+        for event in events.iter() {
+            if let Some(fun) = map.get_mut(&event.token()) {
+                redraw |= fun();
+            }
+        }
+    }
+}
@@ -10,15 +10,104 @@ pub enum Size {
 }
 
 impl Size {
-    pub fn get_height(&self, mon: i32) -> i32 {
+    /* Pixel values are multiplied by the scale of the monitor */
+    pub fn get_height(&self, mon: i32, scale: f64) -> i32 {
         match self {
-            &Size::Pixels(ref size) => *size,
+            &Size::Pixels(ref size) => (*size as f64 * scale).round() as i32,
             &Size::Percent(ref size) => mon * *size / 100,
-            &Size::Font(ref size) => *size * 3 / 2,
+            &Size::Font(ref size) => (*size as f64 * scale).round() as i32 * 3 / 2,
         }
     }
 }
 
+#[derive(Debug, Clone, ConfigAble)]
+pub enum Scale {
+    /* Derived from the physical size RandR reports, 96 DPI being 100% */
+    Auto,
+    Percent(i32),
+}
+
+impl Scale {
+    pub fn get_factor(&self, pixels: u32, mm: u32) -> f64 {
+        match self {
+            &Scale::Percent(ref x) => *x as f64 / 100.0,
+            &Scale::Auto => {
+                /* Projectors and some cheap screens report nothing useful */
+                if mm == 0 {
+                    return 1.0;
+                }
+
+                let dpi = pixels as f64 * 25.4 / mm as f64;
+                /* Stick to quarter steps, and never go below 100% */
+                return ((dpi / 96.0) * 4.0).round().max(4.0) / 4.0;
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, ConfigAble)]
+pub struct MonitorScale {
+    pub monitor: String,
+    pub scale: Scale,
+}
+
+/// A horizontal extent on the bar
+#[derive(Debug, Clone, ConfigAble)]
+pub enum Width {
+    Pixels(i32),
+    Percent(i32),
+}
+
+impl Width {
+    pub fn get_width(&self, bar: i32, scale: f64) -> i32 {
+        match self {
+            &Width::Pixels(ref size) => (*size as f64 * scale).round() as i32,
+            &Width::Percent(ref size) => bar * *size / 100,
+        }
+    }
+}
+
+/// Colour components are in the 0-255 range
+#[derive(Debug, Clone, ConfigAble)]
+pub enum Color {
+    Rgb(i32, i32, i32),
+    Rgba(i32, i32, i32, i32),
+}
+
+impl Color {
+    pub fn to_graphics(&self) -> [f32; 4] {
+        let (r, g, b, a) = match self {
+            &Color::Rgb(r, g, b) => (r, g, b, 255),
+            &Color::Rgba(r, g, b, a) => (r, g, b, a),
+        };
+
+        return [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a as f32 / 255.0];
+    }
+}
+
+#[derive(Debug, Clone, ConfigAble)]
+pub enum SeparatorStyle {
+    Nothing,
+    Line,
+    Gap,
+    Text(String),
+}
+
+#[derive(Debug, Clone, ConfigAble)]
+pub struct Separator {
+    #[ConfigAttrs(default = "SeparatorStyle::Line")]
+    pub style: SeparatorStyle,
+    /* Width of the line or gap. Percent is relative to the bar height */
+    #[ConfigAttrs(default = "Size::Pixels(1)")]
+    pub width: Size,
+    /* Defaults to the foreground of whatever is separated */
+    #[ConfigAttrs(default = "None")]
+    pub color: Option<Color>,
+    /* Space on both sides of the separator. Also used between elements */
+    #[ConfigAttrs(default = "4")]
+    pub padding: i32,
+}
+
 #[derive(Debug, Clone, ConfigAble)]
 pub enum Parser {
     Dzen,
@@ -41,10 +130,53 @@ pub enum Direction {
     Bottom,
 }
 
+/// How content is laid out on bars docked to the left or right
+#[derive(Debug, ConfigAble)]
+pub enum Vertical {
+    /* Turn everything by 90 degrees */
+    Rotate,
+    /* Stack the inputs in rows of the given height in pixels */
+    Rows(i32),
+}
+
+/// How the bar gets drawn
+#[derive(Debug, ConfigAble)]
+pub enum Renderer {
+    /* OpenGL if it's usable, software otherwise */
+    Auto,
+    OpenGL,
+    /* Draw on the CPU. Works everywhere, e.g. in VNC sessions or on Xvfb */
+    Software,
+}
+
+/// Which display server the bar talks to
+#[derive(Debug, ConfigAble)]
+pub enum Backend {
+    /* Wayland if the compositor supports wlr-layer-shell, X otherwise */
+    Auto,
+    X11,
+    /* Needs a compositor with wlr-layer-shell. Always renders in software */
+    Wayland,
+}
+
+/// What is behind the bar's background colour
+#[derive(Debug, ConfigAble)]
+pub enum BackgroundMode {
+    /* The background is always opaque, its alpha is ignored */
+    Solid,
+    /* Use a visual with an alpha channel, so a compositor can blend the bar */
+    Transparent,
+    /* Copy the wallpaper from the root window and draw the background on top.
+     * The alpha of the background colour controls how much it tints */
+    Root,
+}
+
 #[derive(Debug, ConfigAble)]
 pub enum Position {
     Global(Direction),
     Monitor(String, Direction),
+    /* One bar on every monitor */
+    All(Direction),
 }
 
 impl Position {
@@ -52,6 +184,7 @@ impl Position {
         match self {
             &Position::Global(ref dir) => dir,
             &Position::Monitor(_, ref dir) => dir,
+            &Position::All(ref dir) => dir,
         }
     }
 }
@@ -74,18 +207,86 @@ pub struct Input {
     #[ConfigAttrs(default = "Parser::Plain")]
     pub parser: Parser,
     pub position: Anchor,
+    /* Overrides the global foreground for this input */
+    #[ConfigAttrs(default = "None")]
+    pub foreground: Option<Color>,
+    /* Fills the area this input covers on the bar */
+    #[ConfigAttrs(default = "None")]
+    pub background: Option<Color>,
+    /* Content wider than this is cut off with an ellipsis */
+    #[ConfigAttrs(default = "None")]
+    pub max_width: Option<Width>,
+    /* Always reserve at least this much space, even when there's less content */
+    #[ConfigAttrs(default = "None")]
+    pub min_width: Option<Width>,
+    /* Overrides the global separator between elements of this input */
+    #[ConfigAttrs(default = "None")]
+    pub separator: Option<Separator>,
 }
 
 #[derive(Debug, ConfigAble)]
 pub struct Config {
     #[ConfigAttrs(default = "Position::Global(Direction::Top)")]
     pub position: Position,
-    #[ConfigAttrs(default = "vec![Input {source: InputSource::Stdin, layer: 0, name: \"\".into(), parser: Parser::Plain, position: Anchor::Middle}]")]
+    #[ConfigAttrs(default = "vec![Input {source: InputSource::Stdin, layer: 0, name: \"\".into(), parser: Parser::Plain, position: Anchor::Middle, foreground: None, background: None, max_width: None, min_width: None, separator: None}]")]
     pub inputs: Vec<Input>,
     #[ConfigAttrs(default = "\"ongybar\".to_string()")]
     pub title: String,
     #[ConfigAttrs(default = "Size::Pixels(16)")]
-    pub size: Size
+    pub size: Size,
+    /* Used for all monitors not listed in monitor_scales */
+    #[ConfigAttrs(default = "Scale::Percent(100)")]
+    pub scale: Scale,
+    #[ConfigAttrs(default = "Vec::new()")]
+    pub monitor_scales: Vec<MonitorScale>,
+    #[ConfigAttrs(default = "Vertical::Rotate")]
+    pub vertical: Vertical,
+    #[ConfigAttrs(default = "Color::Rgb(0, 0, 0)")]
+    pub background: Color,
+    #[ConfigAttrs(default = "BackgroundMode::Solid")]
+    pub background_mode: BackgroundMode,
+    #[ConfigAttrs(default = "Renderer::Auto")]
+    pub renderer: Renderer,
+    #[ConfigAttrs(default = "Backend::Auto")]
+    pub backend: Backend,
+    /* Used for everything that doesn't set its own colour */
+    #[ConfigAttrs(default = "Color::Rgb(204, 204, 204)")]
+    pub foreground: Color,
+    /* A fontconfig pattern, e.g. "monospace:size=10". Without a size text
+     * is 10 points, or smaller if the bar is too thin for that */
+    #[ConfigAttrs(default = "\"monospace\".to_string()")]
+    pub font: String,
+    /* Used to find images given by name instead of path. Falls back to hicolor */
+    #[ConfigAttrs(default = "\"hicolor\".to_string()")]
+    pub icon_theme: String,
+    /* Images that weren't drawn for this many frames are dropped from the cache.
+     * Drawing all bars once counts as one frame */
+    #[ConfigAttrs(default = "600")]
+    pub image_cache_frames: i32,
+    /* How much memory cached images may use, in KiB */
+    #[ConfigAttrs(default = "65536")]
+    pub image_cache_size: i32,
+    /* Fontconfig patterns tried in order for glyphs missing from font */
+    #[ConfigAttrs(default = "Vec::new()")]
+    pub fallback_fonts: Vec<String>,
+    /* Put between the elements parsed from an input */
+    #[ConfigAttrs(default = "Separator { style: SeparatorStyle::Line, width: Size::Pixels(1), color: None, padding: 4 }")]
+    pub separator: Separator,
+    /* Put between the inputs */
+    #[ConfigAttrs(default = "Separator { style: SeparatorStyle::Gap, width: Size::Percent(50), color: None, padding: 0 }")]
+    pub output_separator: Separator,
+}
+
+impl Config {
+    /// Get the scale factor for the monitor called name
+    pub fn get_scale(&self, name: &str, pixels: u32, mm: u32) -> f64 {
+        let scale = match self.monitor_scales.iter().find(|x| x.monitor == name) {
+            Some(x) => &x.scale,
+            None => &self.scale,
+        };
+
+        return scale.get_factor(pixels, mm);
+    }
 }
 
 /// Get the default config
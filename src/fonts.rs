@@ -0,0 +1,239 @@
+// Font handling
+//
+// Fonts are looked up through fontconfig. This only binds the handful of
+// functions we need to turn a pattern like "monospace:size=10" into a file we
+// can hand to the glyph cache, and the size to draw text at.
+
+extern crate graphics;
+extern crate rusttype;
+
+use modules::renderable::BarTexture;
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::Read;
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::ptr::null_mut;
+
+enum FcPattern {}
+enum FcConfig {}
+
+const FC_MATCH_PATTERN: c_int = 0;
+const FC_RESULT_MATCH: c_int = 0;
+
+#[link(name = "fontconfig")]
+extern "C" {
+    fn FcNameParse(name: *const c_char) -> *mut FcPattern;
+    fn FcConfigSubstitute(config: *mut FcConfig, pattern: *mut FcPattern, kind: c_int) -> c_int;
+    fn FcDefaultSubstitute(pattern: *mut FcPattern);
+    fn FcFontMatch(config: *mut FcConfig, pattern: *mut FcPattern, result: *mut c_int) -> *mut FcPattern;
+    fn FcPatternGetString(pattern: *const FcPattern, object: *const c_char, n: c_int, s: *mut *mut c_char) -> c_int;
+    fn FcPatternGetDouble(pattern: *const FcPattern, object: *const c_char, n: c_int, d: *mut f64) -> c_int;
+    fn FcPatternDestroy(pattern: *mut FcPattern);
+}
+
+/// Get the path of the font fontconfig considers the best match for `pattern`
+pub fn find_font(pattern: &str) -> Option<PathBuf> {
+    let name = match CString::new(pattern) {
+        Ok(x) => x,
+        Err(_) => return None,
+    };
+    let file = CString::new("file").unwrap();
+
+    unsafe {
+        let pat = FcNameParse(name.as_ptr());
+        if pat.is_null() {
+            return None;
+        }
+
+        FcConfigSubstitute(null_mut(), pat, FC_MATCH_PATTERN);
+        FcDefaultSubstitute(pat);
+
+        let mut result = FC_RESULT_MATCH;
+        let font = FcFontMatch(null_mut(), pat, &mut result);
+        FcPatternDestroy(pat);
+
+        if font.is_null() {
+            return None;
+        }
+
+        let mut path: *mut c_char = null_mut();
+        let ret = if FcPatternGetString(font, file.as_ptr(), 0, &mut path) == FC_RESULT_MATCH {
+            Some(PathBuf::from(CStr::from_ptr(path).to_string_lossy().into_owned()))
+        } else {
+            None
+        };
+        /* This also frees the string we got */
+        FcPatternDestroy(font);
+
+        return ret;
+    }
+}
+
+/// Get the font size `pattern` asks for, in points. None if it doesn't set one
+pub fn get_size(pattern: &str) -> Option<f64> {
+    let name = match CString::new(pattern) {
+        Ok(x) => x,
+        Err(_) => return None,
+    };
+    let size = CString::new("size").unwrap();
+    let pixel_size = CString::new("pixelsize").unwrap();
+
+    unsafe {
+        /* Only what's in the pattern itself counts, substitution always adds a size */
+        let pat = FcNameParse(name.as_ptr());
+        if pat.is_null() {
+            return None;
+        }
+
+        let mut value = 0.0;
+        let ret = if FcPatternGetDouble(pat, pixel_size.as_ptr(), 0, &mut value) == FC_RESULT_MATCH {
+            /* Points are 1/72 inch, at 96 DPI like the glyphs are rasterized at */
+            Some(value * 0.75)
+        } else if FcPatternGetDouble(pat, size.as_ptr(), 0, &mut value) == FC_RESULT_MATCH {
+            Some(value)
+        } else {
+            None
+        };
+        FcPatternDestroy(pat);
+
+        return match ret {
+            Some(x) if x > 0.0 => Some(x),
+            _ => None,
+        };
+    }
+}
+
+/// A glyph cache that falls back through an ordered list of fonts.
+///
+/// Each glyph is taken from the first font that has it. This is done for
+/// measuring as well as drawing, so widths always match what ends up on screen.
+/// Glyphs are rasterized with rusttype, so this works with every renderer.
+pub struct FontChain<T> {
+    fonts: Vec<rusttype::Font<'static>>,
+    /* offset, advance and texture, by size and character. None for glyphs
+     * that couldn't be turned into a texture */
+    glyphs: HashMap<(graphics::types::FontSize, char), Option<([f64; 2], [f64; 2], T)>>,
+    /* Handed out in place of glyphs that failed, created with the first font */
+    empty: Option<T>,
+    /* Texture errors are only reported once, they tend to come in bulk */
+    failed: bool,
+}
+
+impl<T: BarTexture> FontChain<T> {
+    pub fn new() -> Self {
+        return FontChain { fonts: Vec::new(), glyphs: HashMap::new(), empty: None, failed: false };
+    }
+
+    /// Append a font to the chain. Fonts pushed first take precedence
+    pub fn push<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let mut data = Vec::new();
+        match File::open(path.as_ref()) {
+            Ok(mut file) => {
+                if let Err(x) = file.read_to_end(&mut data) {
+                    return Err(format!("{}", x));
+                }
+            },
+            Err(x) => return Err(format!("{}", x)),
+        }
+
+        let font = match rusttype::FontCollection::from_bytes(data).into_font() {
+            Some(x) => x,
+            None => return Err(String::from("Not a font rusttype can read")),
+        };
+
+        /* Without this there's nothing to draw when a glyph fails */
+        if self.empty.is_none() {
+            self.empty = Some(T::from_rgba(&[0; 4], 1, 1)?);
+        }
+
+        self.fonts.push(font);
+        return Ok(());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.fonts.is_empty();
+    }
+
+    /* Get the index of the first font that has a glyph for ch.
+     * If none has it, the primary font gets to draw its replacement glyph */
+    fn pick(&self, ch: char) -> usize {
+        for (i, font) in self.fonts.iter().enumerate() {
+            if let Some(glyph) = font.glyph(ch) {
+                if glyph.id().0 != 0 {
+                    return i;
+                }
+            }
+        }
+
+        return 0;
+    }
+
+    /* Rasterize ch into a texture, white with the coverage as alpha.
+     * None if the texture couldn't be created */
+    fn render(&mut self, font_size: graphics::types::FontSize, ch: char)
+              -> Option<([f64; 2], [f64; 2], T)> {
+        let font = &self.fonts[self.pick(ch)];
+        /* Font sizes are in points, rusttype wants pixels */
+        let scale = rusttype::Scale::uniform((font_size as f32 * 1.333).round());
+        let glyph = match font.glyph(ch) {
+            Some(x) => x,
+            None => font.glyph(rusttype::GlyphId(0)).unwrap(),
+        }.scaled(scale);
+        let advance = glyph.h_metrics().advance_width as f64;
+        let glyph = glyph.positioned(rusttype::point(0.0, 0.0));
+
+        /* Keep a pixel of room around the glyph, so filtering doesn't cut it */
+        let (min_x, min_y, width, height) = match glyph.pixel_bounding_box() {
+            Some(bb) => (bb.min.x, bb.min.y, bb.width() as u32 + 2, bb.height() as u32 + 2),
+            None => (0, 0, 1, 1),
+        };
+
+        let mut data = vec![0u8; (width * height * 4) as usize];
+        for pixel in data.chunks_mut(4) {
+            pixel[0] = 255;
+            pixel[1] = 255;
+            pixel[2] = 255;
+        }
+        glyph.draw(|x, y, v| {
+            let pos = ((y + 1) * width + x + 1) as usize * 4;
+            data[pos + 3] = (v * 255.0) as u8;
+        });
+
+        let texture = match T::from_rgba(&data, width, height) {
+            Ok(x) => x,
+            Err(x) => {
+                if !self.failed {
+                    println!("Couldn't create texture for glyph, leaving out glyphs that fail: {}", x);
+                    self.failed = true;
+                }
+                return None;
+            },
+        };
+
+        return Some(([min_x as f64 - 1.0, -min_y as f64 + 1.0], [advance, 0.0], texture));
+    }
+}
+
+impl<T: BarTexture> graphics::character::CharacterCache for FontChain<T> {
+    type Texture = T;
+
+    fn character<'a>(&'a mut self, font_size: graphics::types::FontSize, ch: char)
+                     -> graphics::character::Character<'a, Self::Texture> {
+        if !self.glyphs.contains_key(&(font_size, ch)) {
+            let glyph = self.render(font_size, ch);
+            self.glyphs.insert((font_size, ch), glyph);
+        }
+
+        return match self.glyphs.get(&(font_size, ch)).unwrap() {
+            &Some((offset, size, ref texture)) =>
+                graphics::character::Character { offset: offset, size: size, texture: texture },
+            /* Skipped glyphs don't take any space. There's always a font, so
+             * there's always the empty texture */
+            &None => graphics::character::Character { offset: [0.0, 0.0], size: [0.0, 0.0],
+                                                      texture: self.empty.as_ref().unwrap() },
+        }
+    }
+}
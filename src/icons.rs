@@ -0,0 +1,286 @@
+// Icon themes
+//
+// Resolves icon names like "battery-caution-symbolic" to files, following the
+// freedesktop icon theme specification: the configured theme, the themes it
+// inherits from, and hicolor as the last resort. Only a single size of each
+// icon is drawn, so the lookup picks the one closest to the bar height.
+
+extern crate xdg;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Icons that weren't found and images that failed to load are tried again
+/// after this long, they may have been installed in the meantime
+pub const RETRY_SECONDS: u64 = 5;
+
+/* Tried in order, so bitmaps win over svg at the same size */
+#[cfg(feature = "svg")]
+const EXTENSIONS: [&'static str; 2] = ["png", "svg"];
+/* Without svg support only bitmaps are worth finding */
+#[cfg(not(feature = "svg"))]
+const EXTENSIONS: [&'static str; 1] = ["png"];
+
+enum DirType {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+struct ThemeDir {
+    /* Relative to the theme's directory */
+    name: String,
+    kind: DirType,
+    /* All sizes are in pixels, with the directory's scale already applied */
+    size: u32,
+    min: u32,
+    max: u32,
+    threshold: u32,
+}
+
+impl ThemeDir {
+    /* How far off the icons in here are from size, 0 if they fit */
+    fn distance(&self, size: u32) -> u32 {
+        let (min, max) = match self.kind {
+            DirType::Fixed => (self.size, self.size),
+            DirType::Scalable => (self.min, self.max),
+            DirType::Threshold => (self.size.saturating_sub(self.threshold), self.size + self.threshold),
+        };
+
+        if size < min {
+            return min - size;
+        }
+        if size > max {
+            return size - max;
+        }
+        return 0;
+    }
+}
+
+struct Theme {
+    /* The theme can be split over several base directories */
+    paths: Vec<PathBuf>,
+    dirs: Vec<ThemeDir>,
+    inherits: Vec<String>,
+}
+
+/* Sections of a desktop entry style file, each mapping keys to values */
+fn parse_index(text: &str) -> HashMap<String, HashMap<String, String>> {
+    let mut ret = HashMap::new();
+    let mut section = String::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            section = String::from(&line[1..line.len() - 1]);
+            continue;
+        }
+
+        if let Some(i) = line.find('=') {
+            ret.entry(section.clone()).or_insert_with(HashMap::new)
+                .insert(String::from(line[..i].trim()), String::from(line[i + 1..].trim()));
+        }
+    }
+
+    return ret;
+}
+
+fn split_list(value: Option<&String>) -> Vec<String> {
+    return match value {
+        Some(x) => x.split(',').map(|x| String::from(x.trim()))
+                    .filter(|x| !x.is_empty()).collect(),
+        None => Vec::new(),
+    }
+}
+
+fn get_number(section: &HashMap<String, String>, key: &str) -> Option<u32> {
+    return section.get(key).and_then(|x| x.parse().ok());
+}
+
+/// Finds icon files by name, caching what it found
+pub struct IconLookup {
+    theme: String,
+    bases: Vec<PathBuf>,
+    themes: HashMap<String, Option<Theme>>,
+    /* Misses remember when they were looked up */
+    found: HashMap<(String, u32), Result<String, Instant>>,
+}
+
+impl IconLookup {
+    pub fn new(theme: &str) -> Self {
+        /* The user's icons come first, so they can override the system */
+        let mut bases = Vec::new();
+        if let Ok(home) = ::std::env::var("HOME") {
+            bases.push(PathBuf::from(home).join(".icons"));
+        }
+        if let Ok(x) = xdg::BaseDirectories::new() {
+            bases.push(x.get_data_home().join("icons"));
+            bases.extend(x.get_data_dirs().into_iter().map(|dir| dir.join("icons")));
+        }
+
+        return IconLookup { theme: String::from(theme), bases: bases,
+                            themes: HashMap::new(), found: HashMap::new() };
+    }
+
+    fn load_theme(&self, name: &str) -> Option<Theme> {
+        let paths: Vec<PathBuf> = self.bases.iter().map(|base| base.join(name))
+            .filter(|path| path.is_dir()).collect();
+
+        /* The first index.theme found is the one that counts */
+        let mut text = String::new();
+        let found = paths.iter().any(|path| {
+            File::open(path.join("index.theme"))
+                .and_then(|mut f| f.read_to_string(&mut text)).is_ok()
+        });
+        if !found {
+            return None;
+        }
+
+        let index = parse_index(&text);
+        let main = match index.get("Icon Theme") {
+            Some(x) => x,
+            None => return None,
+        };
+
+        let mut dirs = Vec::new();
+        for name in split_list(main.get("Directories")).into_iter()
+                .chain(split_list(main.get("ScaledDirectories")).into_iter()) {
+            let section = match index.get(&name) {
+                Some(x) => x,
+                None => continue,
+            };
+            let size = match get_number(section, "Size") {
+                Some(x) => x,
+                None => continue,
+            };
+            let scale = get_number(section, "Scale").unwrap_or(1);
+            let kind = match section.get("Type").map(|x| x.as_str()) {
+                Some("Fixed") => DirType::Fixed,
+                Some("Scalable") => DirType::Scalable,
+                _ => DirType::Threshold,
+            };
+
+            dirs.push(ThemeDir { name: name.clone(), kind: kind,
+                                 size: size * scale,
+                                 min: get_number(section, "MinSize").unwrap_or(size) * scale,
+                                 max: get_number(section, "MaxSize").unwrap_or(size) * scale,
+                                 threshold: get_number(section, "Threshold").unwrap_or(2) * scale });
+        }
+
+        return Some(Theme { paths: paths, dirs: dirs, inherits: split_list(main.get("Inherits")) });
+    }
+
+    /* The themes to look through, in order */
+    fn get_chain(&mut self) -> Vec<String> {
+        let mut ret: Vec<String> = Vec::new();
+        let mut todo = vec![self.theme.clone()];
+
+        while !todo.is_empty() {
+            let name = todo.remove(0);
+            if ret.contains(&name) {
+                continue;
+            }
+
+            if !self.themes.contains_key(&name) {
+                let theme = self.load_theme(&name);
+                if theme.is_none() {
+                    println!("Couldn't find icon theme {}", name);
+                }
+                self.themes.insert(name.clone(), theme);
+            }
+
+            /* Depth first, a theme's parents come before its siblings */
+            if let Some(&Some(ref theme)) = self.themes.get(&name) {
+                for (i, parent) in theme.inherits.iter().enumerate() {
+                    todo.insert(i, parent.clone());
+                }
+            }
+            ret.push(name);
+        }
+
+        /* hicolor is always the last resort */
+        if !ret.iter().any(|x| x == "hicolor") {
+            if !self.themes.contains_key("hicolor") {
+                let theme = self.load_theme("hicolor");
+                self.themes.insert(String::from("hicolor"), theme);
+            }
+            ret.push(String::from("hicolor"));
+        }
+
+        return ret;
+    }
+
+    /* The file for name closest to size in the theme */
+    fn find_in_theme(theme: &Theme, name: &str, size: u32) -> Option<String> {
+        let mut best: Option<(u32, PathBuf)> = None;
+
+        for dir in theme.dirs.iter() {
+            let distance = dir.distance(size);
+            if let Some((x, _)) = best {
+                if x <= distance {
+                    continue;
+                }
+            }
+
+            for path in theme.paths.iter() {
+                let found = EXTENSIONS.iter().map(|ext| path.join(&dir.name).join(format!("{}.{}", name, ext)))
+                    .find(|file| file.is_file());
+                if let Some(file) = found {
+                    best = Some((distance, file));
+                    break;
+                }
+            }
+        }
+
+        return best.map(|(_, file)| file.to_string_lossy().into_owned());
+    }
+
+    /// Get the file of the icon called name closest to size pixels
+    pub fn find(&mut self, name: &str, size: u32) -> Option<String> {
+        let key = (String::from(name), size);
+        let missing = match self.found.get(&key) {
+            Some(&Ok(ref x)) => return Some(x.clone()),
+            Some(&Err(when)) => {
+                if when.elapsed() < Duration::from_secs(RETRY_SECONDS) {
+                    return None;
+                }
+                true
+            },
+            None => false,
+        };
+
+        let mut ret = None;
+        for theme in self.get_chain() {
+            if let Some(&Some(ref theme)) = self.themes.get(&theme) {
+                ret = IconLookup::find_in_theme(theme, name, size);
+            }
+            if ret.is_some() {
+                break;
+            }
+        }
+
+        /* Icons that aren't part of any theme */
+        if ret.is_none() {
+            ret = self.bases.iter().map(|base| base.parent().map(|x| x.join("pixmaps")))
+                .filter_map(|x| x)
+                .flat_map(|dir| EXTENSIONS.iter().map(move |ext| dir.join(format!("{}.{}", name, ext))))
+                .find(|file| file.is_file())
+                .map(|file| file.to_string_lossy().into_owned());
+        }
+
+        /* Only complain once, not on every retry */
+        if ret.is_none() && !missing {
+            println!("Couldn't find icon {}", name);
+        }
+
+        self.found.insert(key, ret.clone().ok_or(Instant::now()));
+        return ret;
+    }
+}
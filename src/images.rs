@@ -0,0 +1,321 @@
+// Image loading
+//
+// Most formats are handled by the texture implementations through the image
+// crate. This adds what it can't do: XBM bitmaps like dzen uses them, and SVG
+// icons, which are rasterized through librsvg at the size they're drawn at.
+// SVG support is only built with the svg feature.
+// Producers can also send images inline, as raw pixels or encoded PNG.
+
+extern crate image;
+
+use modules::renderable::BarTexture;
+
+use self::image::ImageDecoder;
+
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+/* Symbolic icons from icon themes are meant to be recoloured */
+fn is_symbolic(path: &str) -> bool {
+    return match Path::new(path).file_stem() {
+        Some(x) => x.to_string_lossy().ends_with("-symbolic"),
+        None => false,
+    }
+}
+
+/// Whether the image is only a mask that gets drawn in the foreground colour
+pub fn is_mask(path: &str) -> bool {
+    return path.ends_with(".xbm") || is_symbolic(path);
+}
+
+/// Whether the image has to be rasterized for the size it is drawn at
+pub fn is_scalable(path: &str) -> bool {
+    return path.ends_with(".svg");
+}
+
+/// Load the image at path. Scalable images are rasterized height pixels high
+pub fn load<T: BarTexture>(path: &str, height: u32) -> Result<T, String> {
+    if path.ends_with(".xbm") {
+        let (width, height, data) = load_xbm(path)?;
+        return T::from_rgba(&data, width, height);
+    }
+
+    if is_symbolic(path) {
+        let (width, height, mut data) = if is_scalable(path) {
+            svg::load_svg(path, height)?
+        } else {
+            match image::open(path) {
+                Ok(x) => {
+                    let x = x.to_rgba();
+                    let (width, height) = x.dimensions();
+                    (width, height, x.into_raw())
+                },
+                Err(x) => return Err(format!("Couldn't load {}: {}", path, x)),
+            }
+        };
+
+        /* Only the shape matters, the colour is the foreground */
+        for pixel in data.chunks_mut(4) {
+            pixel[0] = 255;
+            pixel[1] = 255;
+            pixel[2] = 255;
+        }
+        return T::from_rgba(&data, width, height);
+    }
+
+    if is_scalable(path) {
+        let (width, height, data) = svg::load_svg(path, height)?;
+        return T::from_rgba(&data, width, height);
+    }
+
+    return T::from_path(Path::new(path));
+}
+
+/// Inline images come straight from the input, so this is the most that's
+/// allocated for one. Both for the data in the stream and the decoded pixels
+pub const MAX_INLINE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Image data sent by a producer instead of a path
+pub enum InlineData {
+    /* RGBA, 8 bits per channel. stride is the length of a row in bytes */
+    Rgba { width: u32, height: u32, stride: u32, data: Vec<u8> },
+    Png(Vec<u8>),
+}
+
+/// Create a texture from inline image data
+pub fn load_inline<T: BarTexture>(data: &InlineData) -> Result<T, String> {
+    match data {
+        &InlineData::Rgba { width, height, stride, ref data } => {
+            let row = width as usize * 4;
+            if (stride as usize) < row || data.len() < stride as usize * height as usize {
+                return Err(format!("{}x{} RGBA with a stride of {} doesn't fit into {} bytes",
+                                   width, height, stride, data.len()));
+            }
+            if width == 0 || height == 0 {
+                return Err(String::from("Inline image is empty"));
+            }
+
+            /* Textures want the rows without padding */
+            let pixels: Vec<u8> = data.chunks(stride as usize).take(height as usize)
+                .flat_map(|x| x[..row].iter().cloned()).collect();
+            return T::from_rgba(&pixels, width, height);
+        },
+        &InlineData::Png(ref data) => {
+            /* The header is enough to know how large the pixels get */
+            let (width, height) = match image::png::PNGDecoder::new(Cursor::new(&data[..])).dimensions() {
+                Ok(x) => x,
+                Err(x) => return Err(format!("Couldn't decode inline PNG: {}", x)),
+            };
+            if width as usize * height as usize * 4 > MAX_INLINE_BYTES {
+                return Err(format!("Inline PNG of {}x{} is too large", width, height));
+            }
+
+            let image = match image::load_from_memory_with_format(data, image::ImageFormat::PNG) {
+                Ok(x) => x.to_rgba(),
+                Err(x) => return Err(format!("Couldn't decode inline PNG: {}", x)),
+            };
+            let (width, height) = image.dimensions();
+            return T::from_rgba(&image.into_raw(), width, height);
+        },
+    }
+}
+
+/* Numbers in XBM files are C literals, usually hex */
+fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    if text.starts_with("0x") || text.starts_with("0X") {
+        return u32::from_str_radix(&text[2..], 16).ok();
+    }
+
+    return text.parse().ok();
+}
+
+/* Get the value of "#define <name>_<suffix> <value>" */
+fn get_define(text: &str, suffix: &str) -> Option<u32> {
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("#define") {
+            continue;
+        }
+
+        if let (Some(name), Some(value)) = (words.next(), words.next()) {
+            if name.ends_with(suffix) {
+                return parse_number(value);
+            }
+        }
+    }
+
+    return None;
+}
+
+/// Load an XBM file as white RGBA where bits are set, and transparent where not
+pub fn load_xbm(path: &str) -> Result<(u32, u32, Vec<u8>), String> {
+    let mut text = String::new();
+    if let Err(x) = File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+        return Err(format!("Couldn't read {}: {}", path, x));
+    }
+
+    let width = get_define(&text, "_width").ok_or(format!("{} has no width", path))?;
+    let height = get_define(&text, "_height").ok_or(format!("{} has no height", path))?;
+
+    let bits = match (text.find('{'), text.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &text[start + 1..end],
+        _ => return Err(format!("{} has no bitmap data", path)),
+    };
+    let bytes: Vec<u32> = bits.split(',').filter(|x| !x.trim().is_empty())
+        .filter_map(parse_number).collect();
+
+    /* Rows are padded to full bytes, and the first pixel is the lowest bit */
+    let row = ((width + 7) / 8) as usize;
+    if bytes.len() < row * height as usize {
+        return Err(format!("{} has too little bitmap data", path));
+    }
+
+    let mut data = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            let set = bytes[y * row + x / 8] & (1 << (x % 8)) != 0;
+            let value = if set { 255 } else { 0 };
+            data.extend_from_slice(&[value, value, value, value]);
+        }
+    }
+
+    return Ok((width, height, data));
+}
+
+#[cfg(feature = "svg")]
+mod svg {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_int, c_void};
+    use std::ptr::null_mut;
+
+    enum RsvgHandle {}
+    enum Cairo {}
+    enum CairoSurface {}
+
+    #[repr(C)]
+    struct GError {
+        domain: u32,
+        code: c_int,
+        message: *mut c_char,
+    }
+
+    #[repr(C)]
+    struct RsvgDimensionData {
+        width: c_int,
+        height: c_int,
+        em: f64,
+        ex: f64,
+    }
+
+    const CAIRO_FORMAT_ARGB32: c_int = 0;
+    const CAIRO_STATUS_SUCCESS: c_int = 0;
+
+    /* get_dimensions and render_cairo are deprecated since 2.52, but they
+     * are the only way that works with every 2.x release */
+    #[link(name = "rsvg-2")]
+    extern "C" {
+        fn rsvg_handle_new_from_file(file: *const c_char, error: *mut *mut GError) -> *mut RsvgHandle;
+        fn rsvg_handle_get_dimensions(handle: *mut RsvgHandle, dimensions: *mut RsvgDimensionData);
+        fn rsvg_handle_render_cairo(handle: *mut RsvgHandle, cr: *mut Cairo) -> c_int;
+    }
+
+    #[link(name = "cairo")]
+    extern "C" {
+        fn cairo_image_surface_create(format: c_int, width: c_int, height: c_int) -> *mut CairoSurface;
+        fn cairo_image_surface_get_data(surface: *mut CairoSurface) -> *mut u8;
+        fn cairo_image_surface_get_stride(surface: *mut CairoSurface) -> c_int;
+        fn cairo_surface_status(surface: *mut CairoSurface) -> c_int;
+        fn cairo_surface_flush(surface: *mut CairoSurface);
+        fn cairo_surface_destroy(surface: *mut CairoSurface);
+        fn cairo_create(surface: *mut CairoSurface) -> *mut Cairo;
+        fn cairo_scale(cr: *mut Cairo, x: f64, y: f64);
+        fn cairo_destroy(cr: *mut Cairo);
+    }
+
+    #[link(name = "gobject-2.0")]
+    extern "C" {
+        fn g_object_unref(object: *mut c_void);
+    }
+
+    #[link(name = "glib-2.0")]
+    extern "C" {
+        fn g_error_free(error: *mut GError);
+    }
+
+    /* Render the svg into RGBA pixels, height pixels high */
+    pub fn load_svg(path: &str, height: u32) -> Result<(u32, u32, Vec<u8>), String> {
+        let file = match CString::new(path) {
+            Ok(x) => x,
+            Err(_) => return Err(format!("Invalid path {}", path)),
+        };
+
+        unsafe {
+            let mut error: *mut GError = null_mut();
+            let handle = rsvg_handle_new_from_file(file.as_ptr(), &mut error);
+            if handle.is_null() {
+                let message = if error.is_null() {
+                    String::from("unknown error")
+                } else {
+                    let ret = CStr::from_ptr((*error).message).to_string_lossy().into_owned();
+                    g_error_free(error);
+                    ret
+                };
+                return Err(format!("Couldn't load {}: {}", path, message));
+            }
+
+            let mut dims = RsvgDimensionData { width: 0, height: 0, em: 0.0, ex: 0.0 };
+            rsvg_handle_get_dimensions(handle, &mut dims);
+            if dims.width <= 0 || dims.height <= 0 || height == 0 {
+                g_object_unref(handle as *mut c_void);
+                return Err(format!("{} has no size", path));
+            }
+
+            let scale = height as f64 / dims.height as f64;
+            let width = ((dims.width as f64 * scale).round() as u32).max(1);
+
+            let surface = cairo_image_surface_create(CAIRO_FORMAT_ARGB32, width as c_int, height as c_int);
+            if cairo_surface_status(surface) != CAIRO_STATUS_SUCCESS {
+                cairo_surface_destroy(surface);
+                g_object_unref(handle as *mut c_void);
+                return Err(format!("Couldn't create a surface for {}", path));
+            }
+
+            let cr = cairo_create(surface);
+            cairo_scale(cr, scale, scale);
+            rsvg_handle_render_cairo(handle, cr);
+            cairo_destroy(cr);
+            cairo_surface_flush(surface);
+
+            /* Cairo has premultiplied ARGB in native endian words */
+            let stride = cairo_image_surface_get_stride(surface) as usize;
+            let pixels = cairo_image_surface_get_data(surface);
+            let mut data = Vec::with_capacity((width * height * 4) as usize);
+            for y in 0..height as usize {
+                let line = pixels.offset((y * stride) as isize) as *const u32;
+                for x in 0..width as usize {
+                    let pixel = *line.offset(x as isize);
+                    let a = pixel >> 24;
+                    let unmul = |c: u32| if a == 0 { 0 } else { (c * 255 / a).min(255) as u8 };
+                    data.push(unmul((pixel >> 16) & 0xff));
+                    data.push(unmul((pixel >> 8) & 0xff));
+                    data.push(unmul(pixel & 0xff));
+                    data.push(a as u8);
+                }
+            }
+
+            cairo_surface_destroy(surface);
+            g_object_unref(handle as *mut c_void);
+
+            return Ok((width, height, data));
+        }
+    }
+}
+
+#[cfg(not(feature = "svg"))]
+mod svg {
+    pub fn load_svg(path: &str, _: u32) -> Result<(u32, u32, Vec<u8>), String> {
+        return Err(format!("Can't load {}, ongybar was built without svg support", path));
+    }
+}
@@ -5,24 +5,37 @@ extern crate rs_config;
 extern crate graphics;
 extern crate opengl_graphics;
 extern crate texture;
+extern crate image;
 
 extern crate xdg;
 
+mod backend;
 mod xorg;
 mod config;
+mod fonts;
+mod icons;
+mod images;
 mod modules;
 mod parsers;
+mod software;
+mod surface;
+mod wayland;
 
-use modules::renderable::{Renderable, OngybarState};
+use modules::renderable::{BarTexture, Renderable, OngybarState};
+use modules::renderable::{get_list_size, render_list, render_list_clipped};
 use modules::separator::Separator;
 use modules::ongystr::OngyStr;
 
 use parsers::dzen::dzen_parse;
 use parsers::custom::custom_parse;
 
+use backend::Backend;
+use surface::Surface;
+
 use graphics::Transformed;
 use std::boxed::Box;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::collections::linked_list::LinkedList;
 use std::io::{BufRead, BufReader};
 use std::ops::DerefMut;
@@ -34,81 +47,346 @@ use std::os::unix::io::AsRawFd;
 use std::os::unix::io::FromRawFd;
 use std::ops::Deref;
 
-struct Output<G, C> {
+struct Output<G, C>
+    where G: graphics::Graphics {
     name: String,
     content: Rc<RefCell<LinkedList<Box<Renderable<G, C>>>>>,
     position: config::Anchor,
     layer: i32,
+    foreground: graphics::types::Color,
+    background: Option<graphics::types::Color>,
+    max_width: Option<config::Width>,
+    min_width: Option<config::Width>,
+    /* The space between elements of this output */
+    spacing: f64,
 }
 
-struct Window<G, C> {
-    outputs: Vec<Output<G, C>>,
+/// How outputs are arranged on the window
+#[derive(Clone, Copy)]
+enum Orientation {
+    /// Left to right
+    Horizontal,
+    /// Top to bottom, with everything turned by 90 degrees
+    Rotated,
+    /// Top to bottom, each output in its own row of the given height
+    Rows(u32),
 }
 
-fn render_middle<G, C, R>(g: &mut G, obj: &R, o: &mut OngybarState, c : &mut C,
-                          trans: &graphics::math::Matrix2d, height: u32) -> f64
-    where R: Renderable<G, C> {
-    let size = obj.get_size(c, height, o);
+impl Orientation {
+    fn from_config(conf: &config::Config) -> Self {
+        match conf.position.get_direction() {
+            &config::Direction::Top | &config::Direction::Bottom => Orientation::Horizontal,
+            &config::Direction::Left | &config::Direction::Right => {
+                match &conf.vertical {
+                    &config::Vertical::Rotate => Orientation::Rotated,
+                    &config::Vertical::Rows(x) => Orientation::Rows(std::cmp::max(x, 1) as u32),
+                }
+            },
+        }
+    }
+}
 
-    return obj.do_render(g, height, o, &trans.trans(-size / 2.0, 0f64), c, [0.8, 0.8, 0.8, 1.0]);
+struct Window<G, C>
+    where G: graphics::Graphics {
+    outputs: Vec<Output<G, C>>,
+    orientation: Orientation,
+    background: graphics::types::Color,
+    /* Drawn between outputs, None if there's nothing to draw */
+    separator: Option<Separator>,
+    /* The space on both sides of the separator between outputs */
+    separator_padding: f64,
 }
 
-fn render_right<G, C, R>(g: &mut G, obj: &R, o: &mut OngybarState, c : &mut C,
-                         trans: &graphics::math::Matrix2d, height: u32) -> f64
-    where R: Renderable<G, C> {
-    let size = obj.get_size(c, height, o);
-    return obj.do_render(g, height, o, &trans.trans(-size, 0f64), c, [0.8, 0.8, 0.8, 1.0]);
+/// The slice of the bar an output got assigned during layout.
+/// For vertical bars x and width are along the vertical axis
+struct Placement {
+    x: f64,
+    width: f64,
 }
 
-fn draw_window<'a>(glyphs: &mut opengl_graphics::glyph_cache::GlyphCache<'a>, o: &mut OngybarState,
-                   win: &Window<opengl_graphics::GlGraphics, opengl_graphics::glyph_cache::GlyphCache<'a>>,
-                   graphics : &mut opengl_graphics::GlGraphics,
-                   width: u32, height: u32) {
+/// Assign every output its slice of the bar.
+///
+/// Left and right outputs are placed first, in layer order, each getting at
+/// most what's left over by the outputs before it.
+/// The middle outputs are then placed as one group. It's centered on the whole
+/// bar if there's room, and shifted away from the sides if it would overlap.
+/// Nothing is ever placed over something else, so outputs may get less space
+/// than they asked for.
+fn layout_outputs<G, C>(outputs: &Vec<Output<G, C>>, sizes: &Vec<f64>,
+                        width: f64, gap: f64) -> Vec<Placement>
+    where G: graphics::Graphics {
+    let mut ret: Vec<Placement> =
+        outputs.iter().map(|_| Placement { x: 0.0, width: 0.0 }).collect();
     /* The amount of space covered from the left */
     let mut cover_left = 0.0;
     /* The amount of space covered from the right */
     let mut cover_right = 0.0;
 
+    for (i, output) in outputs.iter().enumerate() {
+        let free = (width - cover_left - cover_right).max(0.0);
+        let size = sizes[i].min(free);
+        match &output.position {
+            &config::Anchor::Left => {
+                ret[i] = Placement { x: cover_left, width: size };
+                cover_left += size + gap;
+            },
+            &config::Anchor::Right => {
+                ret[i] = Placement { x: width - cover_right - size, width: size };
+                cover_right += size + gap;
+            },
+            &config::Anchor::Middle => {},
+        }
+    }
+
+    let middles: Vec<usize> = outputs.iter().enumerate()
+        .filter(|&(_, output)| match &output.position {
+            &config::Anchor::Middle => true,
+            _ => false,
+        })
+        .map(|(i, _)| i).collect();
+
+    if middles.is_empty() {
+        return ret;
+    }
+
+    let total = middles.iter().fold(0.0, |acc, &i| acc + sizes[i])
+                + gap * (middles.len() - 1) as f64;
+    /* The area between the left and right groups */
+    let lower = cover_left.min(width);
+    let upper = (width - cover_right).max(lower);
+
+    let mut start = (width - total) / 2.0;
+    if start + total > upper {
+        start = upper - total;
+    }
+    if start < lower {
+        start = lower;
+    }
+
+    for i in middles {
+        let size = sizes[i].min((upper - start).max(0.0));
+        ret[i] = Placement { x: start, width: size };
+        start += size + gap;
+    }
+
+    return ret;
+}
+
+/// The area of the window something gets drawn into.
+///
+/// Content always flows along the cell's length, and renderables get the
+/// thickness as their height.
+#[derive(Clone, Copy)]
+struct Cell {
+    /* Top left corner in window coordinates */
+    x: f64,
+    y: f64,
+    length: f64,
+    thickness: u32,
+    /* Content flows top to bottom, rather than left to right */
+    rotated: bool,
+}
+
+impl Cell {
+    /* The part of the cell from start to start + length, along the content */
+    fn slice(&self, start: f64, length: f64) -> Self {
+        if self.rotated {
+            return Cell { y: self.y + start, length: length, .. *self };
+        } else {
+            return Cell { x: self.x + start, length: length, .. *self };
+        }
+    }
+
+    /* The GL viewport covering this cell. Anything outside of it is clipped */
+    fn viewport(&self, window_height: u32) -> graphics::Viewport {
+        let (width, height) = if self.rotated {
+            (self.thickness as f64, self.length)
+        } else {
+            (self.length, self.thickness as f64)
+        };
+
+        /* GL has the origin in the bottom left corner */
+        let gl_y = window_height as f64 - self.y - height;
+        return graphics::Viewport { rect: [self.x as i32, gl_y as i32, width as i32, height as i32],
+                                    draw_size: [width as u32, height as u32],
+                                    window_size: [width as u32, height as u32] };
+    }
+
+    /* The transformation that makes content flow along the cell */
+    fn transform(&self, c: &graphics::Context) -> graphics::math::Matrix2d {
+        if self.rotated {
+            return c.transform.trans(self.thickness as f64, 0.0).rot_deg(90.0);
+        } else {
+            return c.transform;
+        }
+    }
+}
+
+fn draw_output<G>(glyphs: &mut fonts::FontChain<G::Texture>, o: &mut OngybarState<G::Texture>,
+                  output: &Output<G, fonts::FontChain<G::Texture>>,
+                  graphics : &mut G,
+                  window_height: u32, cell: Cell, size: f64)
+    where G: Surface,
+          G::Texture: BarTexture {
+    let height = cell.thickness;
+    let list_cell = output.content.borrow();
+    let list = list_cell.deref();
+    let spacing = output.spacing * o.scale;
+
+    if let Some(bg) = output.background {
+        graphics.draw(cell.viewport(window_height), |c, g| {
+            graphics::rectangle(bg, [0.0, 0.0, cell.length, height as f64], cell.transform(&c), g);
+        });
+    }
+
+    if size <= cell.length {
+        graphics.draw(cell.viewport(window_height), |c, g| {
+            render_list(list, spacing, g, height, o, &cell.transform(&c), glyphs, output.foreground);
+        });
+        return;
+    }
+
+    /* The output doesn't fit. Draw as much as we can in front of an ellipsis */
+    let ellipsis = OngyStr(String::from("…"));
+    let ellipsis_width = Renderable::<G, fonts::FontChain<G::Texture>>::get_size(&ellipsis, glyphs, height, o);
+    let room = (cell.length - ellipsis_width).max(0.0);
+    let mut used = 0.0;
+
+    if room >= 1.0 {
+        let content = cell.slice(0.0, room);
+        graphics.draw(content.viewport(window_height), |c, g| {
+            used = render_list_clipped(list, spacing, g, height, o, &content.transform(&c), glyphs,
+                                       output.foreground, room);
+        });
+    }
+    graphics.draw(cell.viewport(window_height), |c, g| {
+        ellipsis.do_render(g, height, o, &cell.transform(&c).trans(used, 0f64),
+                           glyphs, output.foreground);
+    });
+}
+
+fn draw_window<G>(glyphs: &mut fonts::FontChain<G::Texture>, o: &mut OngybarState<G::Texture>,
+                  win: &Window<G, fonts::FontChain<G::Texture>>,
+                  graphics : &mut G,
+                  width: u32, height: u32, scale: f64,
+                  root: Option<&G::Texture>)
+    where G: Surface,
+          G::Texture: BarTexture {
     /* First clear the graphics context */
-    graphics::clear(graphics::color::BLACK, graphics);
+    graphics::clear(win.background, graphics);
+    /* Pseudo transparency, the background only tints the wallpaper */
+    if let Some(image) = root {
+        let full = Cell { x: 0.0, y: 0.0, length: width as f64, thickness: height, rotated: false };
+        graphics.draw(full.viewport(height), |c, g| {
+            graphics::image(image, c.transform, g);
+            graphics::rectangle(win.background, [0.0, 0.0, width as f64, height as f64], c.transform, g);
+        });
+    }
+    o.scale = scale;
+    let separator_padding = win.separator_padding * scale;
+
+    /* The cell the whole bar would be, if it was a single line */
+    let bar = match win.orientation {
+        Orientation::Horizontal => Cell { x: 0.0, y: 0.0, length: width as f64, thickness: height, rotated: false },
+        Orientation::Rotated => Cell { x: 0.0, y: 0.0, length: height as f64, thickness: width, rotated: true },
+        /* Each row is a line on its own, so this is the cell of a row */
+        Orientation::Rows(row) => Cell { x: 0.0, y: 0.0, length: width as f64, thickness: (row as f64 * scale).round() as u32, rotated: false },
+    };
 
-    /* We draw each output */
-    for ref output in &win.outputs {
-        /* The width still available after deducting covered areas from both sides */
-        let width = width as f64 - cover_left - cover_right;
-        /* The rectangle we can draw in now */
-        let draw_rect = [cover_left as i32, 0, width as i32, height as i32];
-        /* The actual GL viewport that will be used for drawing the output */
-        let viewport = graphics::Viewport { rect: draw_rect,
-                                            draw_size: [width as u32, height],
-                                            window_size: [width as u32, height] };
-
-        graphics.draw(viewport, |c, g| {
-            /* Draw the current output */
-            let cell = output.content.borrow();
-            let list = cell.deref();
-            match &output.position {
-                &config::Anchor::Left => {
-                    cover_left += list.do_render(g, height, o, &c.transform, glyphs, [0.8, 0.8, 0.8, 1.0]) + height as f64 / 2.0;
-                },
-                &config::Anchor::Right => {
-                    cover_right += render_right(g, list, o, glyphs, &c.transform.trans(width, 0f64), height) + height as f64 / 2.0;
-                },
-                /* TODO: Make this work with the covering $foo */
-                &config::Anchor::Middle => {
-                    let _ = render_middle(g, list, o, glyphs, &c.transform.trans(width / 2.0, 0f64), height);
-                },
+    /* Measure everything first, so the layout knows what it's working with */
+    let natural: Vec<f64> = win.outputs.iter().map(|output| {
+        let cell = output.content.borrow();
+        get_list_size(cell.deref(), output.spacing * scale, glyphs, bar.thickness, o)
+    }).collect();
+    /* Then apply the configured limits */
+    let sizes: Vec<f64> = win.outputs.iter().zip(natural.iter()).map(|(output, &size)| {
+        let mut size = size;
+        if let Some(ref max) = output.max_width {
+            size = size.min(max.get_width(bar.length as i32, scale) as f64);
+        }
+        if let Some(ref min) = output.min_width {
+            size = size.max(min.get_width(bar.length as i32, scale) as f64);
+        }
+        size.max(0.0).min(bar.length)
+    }).collect();
+
+    if let Orientation::Rows(_) = win.orientation {
+        let row = bar.thickness;
+        /* Every output gets a row. Left goes to the top, right to the bottom */
+        let rows: Vec<f64> = win.outputs.iter().map(|_| row as f64).collect();
+        let places = layout_outputs(&win.outputs, &rows, height as f64, 0.0);
+
+        for ((output, place), (&size, &wanted)) in win.outputs.iter().zip(places.iter()).zip(sizes.iter().zip(natural.iter())) {
+            if place.width < row as f64 || size < 1.0 {
+                continue;
             }
+
+            let cell = Cell { y: place.x, length: size, .. bar };
+            draw_output(glyphs, o, output, graphics, height, cell, wanted);
+        }
+        return;
+    }
+
+    let separator_width = match win.separator {
+        Some(ref x) => Renderable::<G, fonts::FontChain<G::Texture>>::get_size(x, glyphs, bar.thickness, o),
+        None => 0.0,
+    };
+    let gap = separator_width + 2.0 * separator_padding;
+    let places = layout_outputs(&win.outputs, &sizes, bar.length, gap);
+
+    /* We draw each output */
+    for ((output, place), &size) in win.outputs.iter().zip(places.iter()).zip(natural.iter()) {
+        /* Don't bother with outputs that didn't get any space */
+        if place.width < 1.0 {
+            continue;
+        }
+
+        draw_output(glyphs, o, output, graphics, height, bar.slice(place.x, place.width), size);
+    }
+
+    /* Finally the separators between outputs that ended up next to each other */
+    let separator = match win.separator {
+        Some(ref x) => x,
+        None => return,
+    };
+    if separator_width < 1.0 {
+        return;
+    }
+
+    let mut placed: Vec<(&Output<_, _>, &Placement)> =
+        win.outputs.iter().zip(places.iter()).filter(|&(_, place)| place.width >= 1.0).collect();
+    placed.sort_by(|&(_, left), &(_, right)| left.x.partial_cmp(&right.x).unwrap());
+
+    for pair in placed.windows(2) {
+        let (left_out, left) = pair[0];
+        let (right_out, right) = pair[1];
+
+        /* Outputs may have been squeezed together */
+        if right.x - (left.x + left.width) < gap - 0.5 {
+            continue;
+        }
+
+        /* Stick to the right group, otherwise follow whatever is on the left */
+        let x = match &right_out.position {
+            &config::Anchor::Right => right.x - separator_padding - separator_width,
+            _ => left.x + left.width + separator_padding,
+        };
+
+        let cell = bar.slice(x, separator_width);
+        graphics.draw(cell.viewport(height), |c, g| {
+            separator.do_render(g, bar.thickness, o, &cell.transform(&c), glyphs, left_out.foreground);
         });
     }
 }
 
 fn make_update_action<G, C>(source: &config::InputSource,
                             parser: &config::Parser,
+                            separator: &config::Separator,
                             list: Rc<RefCell<LinkedList<Box<Renderable<G, C>>>>>)
                             -> (c_int, Box<FnMut() -> bool>)
-    where C: graphics::character::CharacterCache<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture>  + 'static,
-          G: graphics::Graphics<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static, {
+    where C: graphics::character::CharacterCache<Texture = <G as graphics::Graphics>::Texture> + 'static,
+          G: graphics::Graphics + 'static,
+          <G as graphics::Graphics>::Texture: BarTexture, {
 
     let fd = match source {
         /* STDIN is on FD 0, by definition */
@@ -150,20 +428,21 @@ fn make_update_action<G, C>(source: &config::InputSource,
         },
         &config::Parser::Ongybar => {
             let mut reader = BufReader::new(unsafe {std::fs::File::from_raw_fd(fd)} );
+            let separator = separator.clone();
 
             let fun = move || {
                 let mut first = true;
                 let mut mut_list = list.borrow_mut();
                 mut_list.deref_mut().clear();
 
-                let new_list = custom_parse(&mut reader);
+                let new_list = custom_parse(&mut reader, separator.padding as f64);
 
                 mut_list.clear();
                 for b in new_list {
                     if first {
                         first = false;
-                    } else {
-                        mut_list.push_back(Box::new(Separator));
+                    } else if let Some(sep) = Separator::from_config(&separator) {
+                        mut_list.push_back(Box::new(sep));
                     }
 
                     mut_list.push_back(b);
@@ -176,6 +455,7 @@ fn make_update_action<G, C>(source: &config::InputSource,
         },
         &config::Parser::Dzen => {
             let mut reader = BufReader::new(unsafe {std::fs::File::from_raw_fd(fd)} );
+            let separator = separator.clone();
 
             let fun = move || {
                 let mut first = true;
@@ -184,14 +464,14 @@ fn make_update_action<G, C>(source: &config::InputSource,
                 let mut mut_list = list.borrow_mut();
                 mut_list.deref_mut().clear();
 
-                let new_list = dzen_parse(line.trim());
+                let new_list = dzen_parse(line.trim(), separator.padding as f64);
 
                 mut_list.clear();
                 for b in new_list {
                     if first {
                         first = false;
-                    } else {
-                        mut_list.push_back(Box::new(Separator));
+                    } else if let Some(sep) = Separator::from_config(&separator) {
+                        mut_list.push_back(Box::new(sep));
                     }
 
                     mut_list.push_back(b);
@@ -208,20 +488,27 @@ fn make_update_action<G, C>(source: &config::InputSource,
 }
 
 fn make_outputs<G, C>(conf: &config::Config) -> (Vec<(c_int, Box<FnMut() -> bool>)>, Vec<Output<G, C>>)
-    where C: graphics::character::CharacterCache<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture>  + 'static,
-          G: graphics::Graphics<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static, {
+    where C: graphics::character::CharacterCache<Texture = <G as graphics::Graphics>::Texture> + 'static,
+          G: graphics::Graphics + 'static,
+          <G as graphics::Graphics>::Texture: BarTexture, {
 
     let mut outs = Vec::with_capacity(conf.inputs.len());
     let mut updates = Vec::with_capacity(conf.inputs.len());
 
     for ref input in &conf.inputs {
+        let separator = input.separator.as_ref().unwrap_or(&conf.separator);
         let out = Output {
             name: input.name.clone(),
             position: input.position.clone(),
             layer: input.layer,
             content: Rc::new(RefCell::new(LinkedList::new())),
+            foreground: input.foreground.as_ref().unwrap_or(&conf.foreground).to_graphics(),
+            background: input.background.as_ref().map(|c| c.to_graphics()),
+            max_width: input.max_width.clone(),
+            min_width: input.min_width.clone(),
+            spacing: separator.padding as f64,
         };
-        let update = make_update_action(&input.source, &input.parser, out.content.clone());
+        let update = make_update_action(&input.source, &input.parser, separator, out.content.clone());
 
         outs.push(out);
         updates.push(update);
@@ -233,7 +520,13 @@ fn make_outputs<G, C>(conf: &config::Config) -> (Vec<(c_int, Box<FnMut() -> bool
     return (updates, outs);
 }
 
-fn parse_or_default_config() -> config::Config {
+fn parse_or_default_config(path: Option<&String>) -> config::Config {
+    /* An explicitly given config has to be there */
+    if let Some(x) = path {
+        println!("Using config: {}", x);
+        return rs_config::read_or_exit(std::path::PathBuf::from(x));
+    }
+
     let xdg_base = match xdg::BaseDirectories::with_prefix("ongybar") {
         Ok(x) => x,
         Err(x) => {
@@ -258,21 +551,236 @@ fn parse_or_default_config() -> config::Config {
     }
 }
 
-fn main() {
-    let config = parse_or_default_config();
-
-    let (updates, mut outputs) = make_outputs::<opengl_graphics::GlGraphics, opengl_graphics::glyph_cache::GlyphCache>(&config);
-    let mut settings = texture::TextureSettings::new();
-    settings.set_filter(texture::Filter::Nearest);
-    let mut glyphs =
-        opengl_graphics::glyph_cache::GlyphCache::new(
-            "/usr/share/fonts/TTF/DejaVuSansCode.ttf", settings).unwrap();
+fn load_fonts<T: BarTexture>(pattern: &str, fallbacks: &Vec<String>) -> fonts::FontChain<T> {
+    let mut chain = fonts::FontChain::new();
+
+    for (i, pattern) in std::iter::once(pattern).chain(fallbacks.iter().map(|x| x.as_str())).enumerate() {
+        let path = match fonts::find_font(pattern) {
+            Some(x) => x,
+            None => {
+                println!("Fontconfig couldn't find any font for \"{}\". Is fontconfig set up?", pattern);
+                continue;
+            },
+        };
+
+        match chain.push(&path) {
+            Ok(_) => {
+                if i == 0 {
+                    println!("Using font: {}", path.display());
+                } else {
+                    println!("Using fallback font: {}", path.display());
+                }
+            },
+            Err(x) => {
+                println!("Couldn't load font {} for \"{}\": {}", path.display(), pattern, x);
+            },
+        }
+    }
+
+    if chain.is_empty() {
+        println!("None of the configured fonts could be loaded, giving up");
+        std::process::exit(1);
+    }
+
+    return chain;
+}
+
+/* The colour the bar gets cleared with */
+fn get_background(config: &config::Config) -> graphics::types::Color {
+    let c = config.background.to_graphics();
+    match config.background_mode {
+        config::BackgroundMode::Solid => [c[0], c[1], c[2], 1.0],
+        /* Compositors expect premultiplied alpha */
+        config::BackgroundMode::Transparent => [c[0] * c[3], c[1] * c[3], c[2] * c[3], c[3]],
+        /* Drawn over the wallpaper as tint */
+        config::BackgroundMode::Root => c,
+    }
+}
+
+/* Get the texture for a root image, only uploading it when it changed.
+ * Textures are kept per bar window */
+fn get_root_texture<'a, T: BarTexture>(cache: &'a mut HashMap<u32, (u32, T)>,
+                                       image: Option<&backend::RootImage>)
+                                       -> Option<&'a T> {
+    let image = match image {
+        Some(x) => x,
+        None => return None,
+    };
+
+    /* Older serials are from a previous wallpaper or bars that are gone */
+    cache.retain(|_, &mut (serial, _)| serial == image.serial);
+
+    let fresh = match cache.get(&image.bar) {
+        Some(&(serial, _)) => serial == image.serial,
+        None => false,
+    };
+
+    if !fresh {
+        match T::from_rgba(&image.data, image.width, image.height) {
+            Ok(x) => { cache.insert(image.bar, (image.serial, x)); },
+            Err(x) => {
+                println!("Couldn't upload the root background: {}", x);
+                cache.remove(&image.bar);
+            },
+        }
+    }
+
+    return cache.get(&image.bar).map(|&(_, ref x)| x);
+}
+
+/* Create the inputs and the window contents for the renderer G */
+fn setup<G>(config: &config::Config)
+            -> (Vec<(c_int, Box<FnMut() -> bool>)>,
+                Window<G, fonts::FontChain<G::Texture>>,
+                fonts::FontChain<G::Texture>)
+    where G: Surface + 'static,
+          G::Texture: BarTexture {
+    let (updates, mut outputs) = make_outputs::<G, fonts::FontChain<G::Texture>>(config);
+    let glyphs = load_fonts(&config.font, &config.fallback_fonts);
     outputs.sort_by_key(|ref output| -output.layer);
-    let win = Window { outputs: outputs };
-    let mut state = OngybarState::new();
+    let win = Window { outputs: outputs, background: get_background(config),
+                       orientation: Orientation::from_config(config),
+                       separator: Separator::from_config(&config.output_separator),
+                       separator_padding: config.output_separator.padding as f64 };
+
+    return (updates, win, glyphs);
+}
+
+/* Set everything up for the surfaces of the backend and run the bar */
+fn run<B>(config: &config::Config, mut backend: B)
+    where B: Backend,
+          B::Surface: 'static,
+          <B::Surface as graphics::Graphics>::Texture: BarTexture {
+    let (updates, win, mut glyphs) = setup::<B::Surface>(config);
+    /* Shared with the callback after all bars got drawn */
+    let state = RefCell::new(OngybarState::new(config));
+    let mut roots = HashMap::new();
+
+    backend::run(&mut backend, updates, |g, frame| {
+                     let root = get_root_texture(&mut roots, frame.root);
+                     draw_window(&mut glyphs, &mut state.borrow_mut(), &win, g,
+                                 frame.width, frame.height, frame.scale, root);
+                 },
+                 || state.borrow_mut().end_frame());
+}
+
+fn render_png(config: &config::Config, width: u32, height: u32, path: &str) {
+    let (updates, win, mut glyphs) = setup::<software::Canvas>(config);
+    let mut state = OngybarState::new(config);
+    let mut canvas = software::Canvas::new();
+
+    for (_, mut update) in updates {
+        update();
+    }
+
+    /* There's no monitor to get a size from, so Auto ends up at 100% */
+    let scale = config.scale.get_factor(width, 0);
+    canvas.begin(width, height);
+    draw_window(&mut glyphs, &mut state, &win, &mut canvas, width, height, scale, None);
 
-    xorg::do_x11main(|g, w, h| {
-                         draw_window(&mut glyphs, &mut state, &win, g, w, h); },
-                     || opengl_graphics::GlGraphics::new(opengl_graphics::OpenGL::V3_0),
-                     updates.into_iter(), config.size, config.position);
+    match image::save_buffer(path, &canvas.to_rgba(), width, height, image::ColorType::RGBA(8)) {
+        Ok(_) => println!("Wrote {}x{} bar to {}", width, height, path),
+        Err(x) => {
+            println!("Couldn't write {}: {}", path, x);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// What was asked for on the command line
+struct Args {
+    config: Option<String>,
+    /* Width, height and the file to write to */
+    render: Option<(u32, u32, String)>,
+}
+
+fn usage() -> ! {
+    println!("Usage: ongybar [--config FILE] [--render WIDTHxHEIGHT OUTPUT.png]");
+    std::process::exit(1);
+}
+
+fn parse_args() -> Args {
+    let mut ret = Args { config: None, render: None };
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                ret.config = Some(args.next().unwrap_or_else(|| usage()));
+            },
+            "--render" => {
+                let size = args.next().unwrap_or_else(|| usage());
+                let path = args.next().unwrap_or_else(|| usage());
+                let dims: Vec<u32> = size.split('x').filter_map(|x| x.parse().ok()).collect();
+                if dims.len() != 2 || dims[0] == 0 || dims[1] == 0 {
+                    println!("Couldn't parse size \"{}\", expected something like 1920x16", size);
+                    usage();
+                }
+                ret.render = Some((dims[0], dims[1], path));
+            },
+            _ => usage(),
+        }
+    }
+
+    return ret;
+}
+
+fn main() {
+    let args = parse_args();
+    let config = parse_or_default_config(args.config.as_ref());
+
+    if let Some((width, height, path)) = args.render {
+        render_png(&config, width, height, &path);
+        return;
+    }
+
+    /* The connection that decided for wayland is the one the bars end up on */
+    let compositor = match config.backend {
+        config::Backend::X11 => None,
+        config::Backend::Wayland => match wayland::connect() {
+            Ok(x) => Some(x),
+            Err(x) => {
+                println!("{}", x);
+                std::process::exit(1);
+            },
+        },
+        /* Without WAYLAND_DISPLAY this is an X session, nothing to complain about */
+        config::Backend::Auto if std::env::var("WAYLAND_DISPLAY").is_err() => None,
+        config::Backend::Auto => match wayland::connect() {
+            Ok(x) => Some(x),
+            Err(x) => {
+                println!("{}, falling back to X", x);
+                None
+            },
+        },
+    };
+
+    if let Some(wl) = compositor {
+        run(&config, wayland::WaylandBackend::new(&config, wl));
+        return;
+    }
+
+    let software = match config.renderer {
+        config::Renderer::OpenGL => {
+            /* This prints why, there's nothing to fall back to */
+            if !xorg::gl_available() {
+                std::process::exit(1);
+            }
+            false
+        },
+        config::Renderer::Software => true,
+        config::Renderer::Auto => {
+            let available = xorg::gl_available();
+            if !available {
+                println!("OpenGL isn't usable, falling back to software rendering");
+            }
+            !available
+        },
+    };
+
+    if software {
+        run(&config, xorg::X11Backend::new(&config, || software::Canvas::new(), true));
+    } else {
+        run(&config, xorg::X11Backend::new(&config, || opengl_graphics::GlGraphics::new(opengl_graphics::OpenGL::V3_0), false));
+    }
 }
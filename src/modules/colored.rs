@@ -3,20 +3,46 @@ extern crate graphics;
 use modules::renderable::Renderable;
 use modules::renderable::OngybarState;
 
-pub struct Colored<G, C> {
-    pub color: graphics::types::Color,
+pub struct Colored<G, C>
+    where G: graphics::Graphics {
+    /* The foreground colour. None keeps whatever the parent uses */
+    pub color: Option<graphics::types::Color>,
+    /* The background. Fills the full cell height behind the element */
+    pub background: Option<graphics::types::Color>,
     pub elem: Box<Renderable<G, C>>,
 }
 
-impl<G, C> Renderable<G, C> for Colored<G, C> {
-    fn get_size(&self, cache: &mut C, height: u32, o: &mut OngybarState) -> f64 {
+impl<G, C> Renderable<G, C> for Colored<G, C>
+    where G: graphics::Graphics {
+    fn get_size(&self, cache: &mut C, height: u32, o: &mut OngybarState<G::Texture>) -> f64 {
         return self.elem.get_size(cache, height, o);
     }
 
-    fn do_render(&self, g: &mut G, height: u32, o: &mut OngybarState,
+    fn do_render(&self, g: &mut G, height: u32, o: &mut OngybarState<G::Texture>,
                  trans: &graphics::math::Matrix2d, cache: &mut C,
-                 _: graphics::types::Color) -> f64 {
-        return self.elem.do_render(g, height, o, trans, cache, self.color);
+                 color: graphics::types::Color) -> f64 {
+        if let Some(bg) = self.background {
+            let width = self.elem.get_size(cache, height, o);
+            graphics::rectangle(bg, [0.0, 0.0, width, height as f64], *trans, g);
+        }
+
+        return self.elem.do_render(g, height, o, trans, cache,
+                                   self.color.unwrap_or(color));
+    }
+
+    fn get_background(&self) -> Option<graphics::types::Color> {
+        return self.background.or_else(|| self.elem.get_background());
     }
-}
 
+    fn do_render_clipped(&self, g: &mut G, height: u32, o: &mut OngybarState<G::Texture>,
+                         trans: &graphics::math::Matrix2d, cache: &mut C,
+                         color: graphics::types::Color, width: f64) -> f64 {
+        if let Some(bg) = self.background {
+            let size = self.elem.get_size(cache, height, o).min(width);
+            graphics::rectangle(bg, [0.0, 0.0, size, height as f64], *trans, g);
+        }
+
+        return self.elem.do_render_clipped(g, height, o, trans, cache,
+                                           self.color.unwrap_or(color), width);
+    }
+}
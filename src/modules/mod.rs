@@ -6,3 +6,4 @@ pub mod separator;
 pub mod ongyrect;
 pub mod ongyimage;
 pub mod ongydraw;
+pub mod ongylist;
@@ -4,7 +4,7 @@ use std;
 use modules::renderable::Renderable;
 use modules::renderable::OngybarState;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Coordtype {
     Absolute,
     Relative,
@@ -12,19 +12,82 @@ pub enum Coordtype {
 }
 
 impl Coordtype {
-    fn transform(self, x: f64, y: f64, height: u32) -> (f64, f64) {
+    /* Absolute values are pixels, so they get multiplied by the monitor scale */
+    fn transform(self, x: f64, y: f64, height: u32, scale: f64) -> (f64, f64) {
         return match self {
             Coordtype::Absolute =>
-                (x, y),
+                (x * scale, y * scale),
             Coordtype::Relative =>
                 (x / 100.0 * height as f64, y / 100.0 * height as f64),
             Coordtype::SemiRelative =>
-                (x, y * height as f64 / 100.0),
+                (x * scale, y * height as f64 / 100.0),
         }
     }
+
+    /* Widths and radii scale like x does */
+    fn length(self, l: f64, height: u32, scale: f64) -> f64 {
+        let (ret, _) = self.transform(l, l, height, scale);
+        return ret;
+    }
+}
+
+/* Points on a circle, angles are degrees clockwise from the top */
+fn arc_points(x: f64, y: f64, radius: f64, start: f64, end: f64) -> Vec<[f64; 2]> {
+    let end = if end < start { end + 360.0 } else { end };
+    let span = (end - start).to_radians();
+    /* About one point every 2 pixels along the circle is smooth enough */
+    let steps = ((span * radius / 2.0).ceil() as usize).max(4).min(360);
+
+    return (0..steps + 1).map(|i| {
+        let angle = start.to_radians() + span * i as f64 / steps as f64;
+        [x + radius * angle.sin(), y - radius * angle.cos()]
+    }).collect();
+}
+
+/* The part of a ring between inner and outer radius, in convex pieces */
+fn draw_ring<G>(g: &mut G, c: graphics::types::Color, trans: graphics::math::Matrix2d,
+                x: f64, y: f64, inner: f64, outer: f64, start: f64, end: f64)
+    where G: graphics::Graphics {
+    let inside = arc_points(x, y, inner.max(0.0), start, end);
+    let outside = arc_points(x, y, outer, start, end);
+
+    for i in 1..outside.len() {
+        let quad = [inside[i - 1], outside[i - 1], outside[i], inside[i]];
+        graphics::polygon(c, &quad, trans, g);
+    }
+}
+
+/* Lines between consecutive points, and back to the first one if closed */
+fn draw_segments<G>(g: &mut G, c: graphics::types::Color, trans: graphics::math::Matrix2d,
+                    points: &[[f64; 2]], width: f64, closed: bool)
+    where G: graphics::Graphics {
+    for pair in points.windows(2) {
+        graphics::line(c, width / 2.0, [pair[0][0], pair[0][1], pair[1][0], pair[1][1]], trans, g);
+    }
+
+    if closed && points.len() > 2 {
+        let (first, last) = (points[0], points[points.len() - 1]);
+        graphics::line(c, width / 2.0, [last[0], last[1], first[0], first[1]], trans, g);
+    }
 }
 
-#[derive(Debug)]
+/* The right most x of the points */
+fn right(points: &[[f64; 2]]) -> f64 {
+    return points.iter().fold(0.0, |acc, p| acc.max(p[0]));
+}
+
+/* How wide the points spread horizontally */
+fn extent(points: &[[f64; 2]]) -> f64 {
+    if points.is_empty() {
+        return 0.0;
+    }
+
+    let min = points.iter().fold(std::f64::MAX, |acc, p| acc.min(p[0]));
+    let max = points.iter().fold(std::f64::MIN, |acc, p| acc.max(p[0]));
+    return max - min;
+}
+
+#[derive(Debug, PartialEq)]
 pub struct DrawRect {
     x1: f64,
     y1: f64,
@@ -34,17 +97,23 @@ pub struct DrawRect {
 }
 
 impl DrawRect {
-    fn get_width(&self, height: u32, coords: Coordtype) -> f64{
-        let (x, _) = coords.transform(self.x2 - self.x1, self.y2 - self.y1, height);
+    fn get_width(&self, height: u32, coords: Coordtype, scale: f64) -> f64{
+        let (x, _) = coords.transform(self.x2 - self.x1, self.y2 - self.y1, height, scale);
         return x;
     }
 
-    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype,
+    fn get_right(&self, height: u32, coords: Coordtype, scale: f64) -> f64 {
+        let (x1, _) = coords.transform(self.x1, self.y1, height, scale);
+        let (x2, _) = coords.transform(self.x2, self.y2, height, scale);
+        return x1.max(x2);
+    }
+
+    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype, scale: f64,
                  trans: graphics::math::Matrix2d,
                  c: graphics::types::Color) -> f64
         where  G: graphics::Graphics {
-        let (x, y) = coords.transform(self.x1, self.y1, height);
-        let (width, height) = coords.transform(self.x2 - self.x1, self.y2 - self.y1, height);
+        let (x, y) = coords.transform(self.x1, self.y1, height, scale);
+        let (width, height) = coords.transform(self.x2 - self.x1, self.y2 - self.y1, height, scale);
 
         let dims = [x, y, width, height];
         graphics::rectangle(c, dims, trans, g);
@@ -57,22 +126,384 @@ impl DrawRect {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
+pub struct DrawLine {
+    x1: f64,
+    y1: f64,
+
+    x2: f64,
+    y2: f64,
+    width: f64,
+}
+
+impl DrawLine {
+    fn get_width(&self, height: u32, coords: Coordtype, scale: f64) -> f64{
+        let (x1, _) = coords.transform(self.x1, self.y1, height, scale);
+        let (x2, _) = coords.transform(self.x2, self.y2, height, scale);
+        return (x2 - x1).abs();
+    }
+
+    fn get_right(&self, height: u32, coords: Coordtype, scale: f64) -> f64 {
+        let (x1, _) = coords.transform(self.x1, self.y1, height, scale);
+        let (x2, _) = coords.transform(self.x2, self.y2, height, scale);
+        return x1.max(x2) + coords.length(self.width, height, scale) / 2.0;
+    }
+
+    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype, scale: f64,
+                 trans: graphics::math::Matrix2d,
+                 c: graphics::types::Color) -> f64
+        where  G: graphics::Graphics {
+        let (x1, y1) = coords.transform(self.x1, self.y1, height, scale);
+        let (x2, y2) = coords.transform(self.x2, self.y2, height, scale);
+        let width = coords.length(self.width, height, scale);
+
+        graphics::line(c, width / 2.0, [x1, y1, x2, y2], trans, g);
+
+        return (x2 - x1).abs();
+    }
+
+    pub fn new(x1: f64, y1: f64, x2: f64, y2: f64, width: f64) -> Self {
+        DrawLine { x1: x1, y1: y1, x2: x2, y2: y2, width: width }
+    }
+}
+
+/// A circle, or its outline if width isn't 0
+#[derive(Debug, PartialEq)]
+pub struct DrawCircle {
+    x: f64,
+    y: f64,
+    radius: f64,
+    width: f64,
+}
+
+impl DrawCircle {
+    fn get_width(&self, height: u32, coords: Coordtype, scale: f64) -> f64{
+        return coords.length(self.radius, height, scale) * 2.0;
+    }
+
+    fn get_right(&self, height: u32, coords: Coordtype, scale: f64) -> f64 {
+        let (x, _) = coords.transform(self.x, self.y, height, scale);
+        return x + coords.length(self.radius, height, scale);
+    }
+
+    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype, scale: f64,
+                 trans: graphics::math::Matrix2d,
+                 c: graphics::types::Color) -> f64
+        where  G: graphics::Graphics {
+        let (x, y) = coords.transform(self.x, self.y, height, scale);
+        let radius = coords.length(self.radius, height, scale);
+        let width = coords.length(self.width, height, scale);
+
+        if width > 0.0 {
+            draw_ring(g, c, trans, x, y, radius - width, radius, 0.0, 360.0);
+        } else {
+            graphics::ellipse(c, [x - radius, y - radius, radius * 2.0, radius * 2.0], trans, g);
+        }
+
+        return radius * 2.0;
+    }
+
+    pub fn new(x: f64, y: f64, radius: f64, width: f64) -> Self {
+        DrawCircle { x: x, y: y, radius: radius, width: width }
+    }
+}
+
+/// Part of a circle, going clockwise from start to end in degrees with 0 at
+/// the top. Filled like a pie slice if width is 0, a gauge otherwise
+#[derive(Debug, PartialEq)]
+pub struct DrawArc {
+    x: f64,
+    y: f64,
+    radius: f64,
+    start: f64,
+    end: f64,
+    width: f64,
+}
+
+impl DrawArc {
+    fn get_width(&self, height: u32, coords: Coordtype, scale: f64) -> f64{
+        return coords.length(self.radius, height, scale) * 2.0;
+    }
+
+    fn get_right(&self, height: u32, coords: Coordtype, scale: f64) -> f64 {
+        let (x, _) = coords.transform(self.x, self.y, height, scale);
+        return x + coords.length(self.radius, height, scale);
+    }
+
+    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype, scale: f64,
+                 trans: graphics::math::Matrix2d,
+                 c: graphics::types::Color) -> f64
+        where  G: graphics::Graphics {
+        let (x, y) = coords.transform(self.x, self.y, height, scale);
+        let radius = coords.length(self.radius, height, scale);
+        let width = coords.length(self.width, height, scale);
+
+        if width > 0.0 {
+            draw_ring(g, c, trans, x, y, radius - width, radius, self.start, self.end);
+        } else {
+            /* Polygons are filled as fan around the first point, so starting
+             * in the center works for slices of any size */
+            let mut points = vec![[x, y]];
+            points.extend(arc_points(x, y, radius, self.start, self.end));
+            graphics::polygon(c, &points, trans, g);
+        }
+
+        return radius * 2.0;
+    }
+
+    pub fn new(x: f64, y: f64, radius: f64, start: f64, end: f64, width: f64) -> Self {
+        DrawArc { x: x, y: y, radius: radius, start: start, end: end, width: width }
+    }
+}
+
+/// A closed shape. Filled if width is 0, which only works for convex shapes
+#[derive(Debug, PartialEq)]
+pub struct DrawPolygon {
+    points: Vec<(f64, f64)>,
+    width: f64,
+}
+
+impl DrawPolygon {
+    fn get_points(&self, height: u32, coords: Coordtype, scale: f64) -> Vec<[f64; 2]> {
+        return self.points.iter().map(|&(x, y)| {
+            let (x, y) = coords.transform(x, y, height, scale);
+            [x, y]
+        }).collect();
+    }
+
+    fn get_width(&self, height: u32, coords: Coordtype, scale: f64) -> f64{
+        return extent(&self.get_points(height, coords, scale));
+    }
+
+    fn get_right(&self, height: u32, coords: Coordtype, scale: f64) -> f64 {
+        let points = self.get_points(height, coords, scale);
+        return right(&points) + coords.length(self.width, height, scale) / 2.0;
+    }
+
+    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype, scale: f64,
+                 trans: graphics::math::Matrix2d,
+                 c: graphics::types::Color) -> f64
+        where  G: graphics::Graphics {
+        let points = self.get_points(height, coords, scale);
+        let width = coords.length(self.width, height, scale);
+
+        if width > 0.0 {
+            draw_segments(g, c, trans, &points, width, true);
+        } else if points.len() > 2 {
+            graphics::polygon(c, &points, trans, g);
+        }
+
+        return extent(&points);
+    }
+
+    pub fn new(points: Vec<(f64, f64)>, width: f64) -> Self {
+        DrawPolygon { points: points, width: width }
+    }
+}
+
+/// Lines through all points, e.g. for graphs
+#[derive(Debug, PartialEq)]
+pub struct DrawPolyline {
+    points: Vec<(f64, f64)>,
+    width: f64,
+}
+
+impl DrawPolyline {
+    fn get_points(&self, height: u32, coords: Coordtype, scale: f64) -> Vec<[f64; 2]> {
+        return self.points.iter().map(|&(x, y)| {
+            let (x, y) = coords.transform(x, y, height, scale);
+            [x, y]
+        }).collect();
+    }
+
+    fn get_width(&self, height: u32, coords: Coordtype, scale: f64) -> f64{
+        return extent(&self.get_points(height, coords, scale));
+    }
+
+    fn get_right(&self, height: u32, coords: Coordtype, scale: f64) -> f64 {
+        let points = self.get_points(height, coords, scale);
+        return right(&points) + coords.length(self.width, height, scale) / 2.0;
+    }
+
+    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype, scale: f64,
+                 trans: graphics::math::Matrix2d,
+                 c: graphics::types::Color) -> f64
+        where  G: graphics::Graphics {
+        let points = self.get_points(height, coords, scale);
+        let width = coords.length(self.width, height, scale);
+
+        draw_segments(g, c, trans, &points, width, false);
+
+        return extent(&points);
+    }
+
+    pub fn new(points: Vec<(f64, f64)>, width: f64) -> Self {
+        DrawPolyline { points: points, width: width }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PathSegment {
+    Move(f64, f64),
+    Line(f64, f64),
+    /* Cubic bezier through two control points to the end point */
+    Curve(f64, f64, f64, f64, f64, f64),
+    /* Back to where the current sub path started */
+    Close,
+}
+
+/// A path built from segments. Filled if width is 0, which only works for
+/// convex sub paths
+#[derive(Debug, PartialEq)]
+pub struct DrawPath {
+    segments: Vec<PathSegment>,
+    width: f64,
+}
+
+impl DrawPath {
+    /* The sub paths as points with curves flattened, and whether they are closed */
+    fn get_subpaths(&self, height: u32, coords: Coordtype, scale: f64) -> Vec<(Vec<[f64; 2]>, bool)> {
+        let mut ret = Vec::new();
+        let mut current: Vec<[f64; 2]> = Vec::new();
+
+        for segment in &self.segments {
+            match segment {
+                &PathSegment::Move(x, y) => {
+                    if current.len() > 1 {
+                        ret.push((current, false));
+                    }
+                    let (x, y) = coords.transform(x, y, height, scale);
+                    current = vec![[x, y]];
+                },
+                &PathSegment::Line(x, y) => {
+                    let (x, y) = coords.transform(x, y, height, scale);
+                    current.push([x, y]);
+                },
+                &PathSegment::Curve(x1, y1, x2, y2, x, y) => {
+                    let start = match current.last() {
+                        Some(x) => *x,
+                        None => [0.0, 0.0],
+                    };
+                    let (x1, y1) = coords.transform(x1, y1, height, scale);
+                    let (x2, y2) = coords.transform(x2, y2, height, scale);
+                    let (x, y) = coords.transform(x, y, height, scale);
+
+                    /* The control polygon is longer than the curve, so this
+                     * gives at least a point every 2 pixels */
+                    let length = (x1 - start[0]).hypot(y1 - start[1])
+                                 + (x2 - x1).hypot(y2 - y1) + (x - x2).hypot(y - y2);
+                    let steps = ((length / 2.0).ceil() as usize).max(4).min(256);
+                    for i in 1..steps + 1 {
+                        let t = i as f64 / steps as f64;
+                        let u = 1.0 - t;
+                        let a = u * u * u;
+                        let b = 3.0 * u * u * t;
+                        let c = 3.0 * u * t * t;
+                        let d = t * t * t;
+                        current.push([a * start[0] + b * x1 + c * x2 + d * x,
+                                      a * start[1] + b * y1 + c * y2 + d * y]);
+                    }
+                },
+                &PathSegment::Close => {
+                    let start = current.first().cloned();
+                    if current.len() > 1 {
+                        ret.push((current, true));
+                    }
+                    /* Following segments continue from the start */
+                    current = start.into_iter().collect();
+                },
+            }
+        }
+
+        if current.len() > 1 {
+            ret.push((current, false));
+        }
+
+        return ret;
+    }
+
+    fn get_width(&self, height: u32, coords: Coordtype, scale: f64) -> f64{
+        let points: Vec<[f64; 2]> = self.get_subpaths(height, coords, scale).into_iter()
+            .flat_map(|(points, _)| points).collect();
+        return extent(&points);
+    }
+
+    fn get_right(&self, height: u32, coords: Coordtype, scale: f64) -> f64 {
+        let width = coords.length(self.width, height, scale);
+        return self.get_subpaths(height, coords, scale).iter()
+            .fold(0.0, |acc, &(ref points, _)| acc.max(right(points) + width / 2.0));
+    }
+
+    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype, scale: f64,
+                 trans: graphics::math::Matrix2d,
+                 c: graphics::types::Color) -> f64
+        where  G: graphics::Graphics {
+        let width = coords.length(self.width, height, scale);
+
+        for (points, closed) in self.get_subpaths(height, coords, scale) {
+            if width > 0.0 {
+                draw_segments(g, c, trans, &points, width, closed);
+            } else if points.len() > 2 {
+                graphics::polygon(c, &points, trans, g);
+            }
+        }
+
+        return self.get_width(height, coords, scale);
+    }
+
+    pub fn new(segments: Vec<PathSegment>, width: f64) -> Self {
+        DrawPath { segments: segments, width: width }
+    }
+}
+
+/// Overrides the coordinate type for the contained command
+#[derive(Debug, PartialEq)]
+pub struct DrawCoords {
+    v: Box<DrawCommand>,
+    coords: Coordtype,
+}
+
+impl DrawCoords {
+    fn get_width(&self, height: u32, _: Coordtype, scale: f64) -> f64{
+        return self.v.get_width(height, self.coords, scale);
+    }
+
+    fn get_right(&self, height: u32, _: Coordtype, scale: f64) -> f64 {
+        return self.v.get_right(height, self.coords, scale);
+    }
+
+    fn do_render<G> (&self, g: &mut G, height: u32, _: Coordtype, scale: f64,
+                 trans: graphics::math::Matrix2d,
+                 c: graphics::types::Color) -> f64
+        where  G: graphics::Graphics {
+        return self.v.do_render(g, height, self.coords, scale, trans, c);
+    }
+
+    pub fn new(v: DrawCommand, coords: Coordtype) -> Self {
+        DrawCoords {v: Box::new(v), coords: coords}
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct DrawCol {
     v: Box<DrawCommand>,
     c: graphics::types::Color,
 }
 
 impl DrawCol {
-    fn get_width(&self, height: u32, coords: Coordtype) -> f64{
-        return self.v.get_width(height, coords);
+    fn get_width(&self, height: u32, coords: Coordtype, scale: f64) -> f64{
+        return self.v.get_width(height, coords, scale);
+    }
+
+    fn get_right(&self, height: u32, coords: Coordtype, scale: f64) -> f64 {
+        return self.v.get_right(height, coords, scale);
     }
 
-    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype,
+    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype, scale: f64,
                  trans: graphics::math::Matrix2d,
                  _: graphics::types::Color) -> f64
         where  G: graphics::Graphics {
-        return self.v.do_render(g, height, coords, trans, self.c);
+        return self.v.do_render(g, height, coords, scale, trans, self.c);
     }
 
     pub fn new(v: DrawCommand, c: graphics::types::Color) -> Self {
@@ -80,40 +511,98 @@ impl DrawCol {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum DrawCommand {
     Rect(DrawRect),
+    Line(DrawLine),
     Col(DrawCol),
+    Circle(DrawCircle),
+    Arc(DrawArc),
+    Polygon(DrawPolygon),
+    Polyline(DrawPolyline),
+    Path(DrawPath),
+    Coords(DrawCoords),
 }
 
 impl DrawCommand {
-    fn get_width(&self, height: u32, coords: Coordtype) -> f64{
+    fn get_width(&self, height: u32, coords: Coordtype, scale: f64) -> f64{
         return match self {
-            &DrawCommand::Rect(ref x) => x.get_width(height, coords),
-            &DrawCommand::Col(ref x) => x.get_width(height, coords),
+            &DrawCommand::Rect(ref x) => x.get_width(height, coords, scale),
+            &DrawCommand::Line(ref x) => x.get_width(height, coords, scale),
+            &DrawCommand::Col(ref x) => x.get_width(height, coords, scale),
+            &DrawCommand::Circle(ref x) => x.get_width(height, coords, scale),
+            &DrawCommand::Arc(ref x) => x.get_width(height, coords, scale),
+            &DrawCommand::Polygon(ref x) => x.get_width(height, coords, scale),
+            &DrawCommand::Polyline(ref x) => x.get_width(height, coords, scale),
+            &DrawCommand::Path(ref x) => x.get_width(height, coords, scale),
+            &DrawCommand::Coords(ref x) => x.get_width(height, coords, scale),
         }
     }
 
-    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype,
+    /* The right most point drawn, relative to where the element starts */
+    fn get_right(&self, height: u32, coords: Coordtype, scale: f64) -> f64 {
+        return match self {
+            &DrawCommand::Rect(ref x) => x.get_right(height, coords, scale),
+            &DrawCommand::Line(ref x) => x.get_right(height, coords, scale),
+            &DrawCommand::Col(ref x) => x.get_right(height, coords, scale),
+            &DrawCommand::Circle(ref x) => x.get_right(height, coords, scale),
+            &DrawCommand::Arc(ref x) => x.get_right(height, coords, scale),
+            &DrawCommand::Polygon(ref x) => x.get_right(height, coords, scale),
+            &DrawCommand::Polyline(ref x) => x.get_right(height, coords, scale),
+            &DrawCommand::Path(ref x) => x.get_right(height, coords, scale),
+            &DrawCommand::Coords(ref x) => x.get_right(height, coords, scale),
+        }
+    }
+
+    fn do_render<G> (&self, g: &mut G, height: u32, coords: Coordtype, scale: f64,
                  trans: graphics::math::Matrix2d,
                  c: graphics::types::Color) -> f64
         where  G: graphics::Graphics {
         return match self {
-            &DrawCommand::Rect(ref x) => x.do_render(g, height, coords, trans, c),
-            &DrawCommand::Col(ref x) => x.do_render(g, height, coords, trans, c),
+            &DrawCommand::Rect(ref x) => x.do_render(g, height, coords, scale, trans, c),
+            &DrawCommand::Line(ref x) => x.do_render(g, height, coords, scale, trans, c),
+            &DrawCommand::Col(ref x) => x.do_render(g, height, coords, scale, trans, c),
+            &DrawCommand::Circle(ref x) => x.do_render(g, height, coords, scale, trans, c),
+            &DrawCommand::Arc(ref x) => x.do_render(g, height, coords, scale, trans, c),
+            &DrawCommand::Polygon(ref x) => x.do_render(g, height, coords, scale, trans, c),
+            &DrawCommand::Polyline(ref x) => x.do_render(g, height, coords, scale, trans, c),
+            &DrawCommand::Path(ref x) => x.do_render(g, height, coords, scale, trans, c),
+            &DrawCommand::Coords(ref x) => x.do_render(g, height, coords, scale, trans, c),
         }
     }
 }
 
+/// How much space an OngyDraw takes up on the bar
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DrawWidth {
+    /* The widths of all commands added up, what the first draw format does */
+    Sum,
+    /* Up to the right most point anything is drawn at */
+    Extents,
+    /* In the x units of the element's Coordtype */
+    Fixed(f64),
+}
+
 pub struct OngyDraw {
     coords: Coordtype,
+    width: DrawWidth,
     values: Vec<DrawCommand>,
 }
 
 impl OngyDraw {
-    pub fn new<I> (coords: Coordtype, vals: I) -> Self
+    pub fn new<I> (coords: Coordtype, width: DrawWidth, vals: I) -> Self
         where I: std::iter::Iterator<Item=DrawCommand> {
-        OngyDraw { coords: coords, values: vals.collect() }
+        OngyDraw { coords: coords, width: width, values: vals.collect() }
+    }
+
+    fn get_width(&self, height: u32, scale: f64) -> f64 {
+        return match self.width {
+            DrawWidth::Sum =>
+                self.values.iter().fold(0.0, |acc, v| acc + v.get_width(height, self.coords, scale)),
+            DrawWidth::Extents =>
+                self.values.iter().fold(0.0, |acc, v| acc.max(v.get_right(height, self.coords, scale))),
+            DrawWidth::Fixed(x) => self.coords.length(x, height, scale),
+        }
     }
 }
 
@@ -122,23 +611,17 @@ impl<G, C> Renderable<G, C> for OngyDraw
     where C: graphics::character::CharacterCache,
           G: graphics::Graphics {
 
-    fn get_size(&self, _: &mut C, h: u32, _: &mut OngybarState) -> f64 {
-        let mut sum = 0.0;
-        for ref v in &self.values {
-            sum += v.get_width(h, self.coords);
-        }
-
-        return sum;
+    fn get_size(&self, _: &mut C, h: u32, o: &mut OngybarState<G::Texture>) -> f64 {
+        return self.get_width(h, o.scale);
     }
 
-    fn do_render(&self, g: &mut G, height: u32, _: &mut OngybarState,
+    fn do_render(&self, g: &mut G, height: u32, o: &mut OngybarState<G::Texture>,
                  trans: &graphics::math::Matrix2d, _: &mut C,
                  c: graphics::types::Color) -> f64 {
-        let mut sum = 0.0;
         for ref v in &self.values {
-            sum += v.do_render(g, height, self.coords, *trans, c);
+            v.do_render(g, height, self.coords, o.scale, *trans, c);
         }
 
-        return sum;
+        return self.get_width(height, o.scale);
     }
 }
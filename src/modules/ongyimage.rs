@@ -1,34 +1,141 @@
 extern crate graphics;
-extern crate opengl_graphics;
 
+use images;
 use modules::renderable::Renderable;
-use modules::renderable::OngybarState;
+use modules::renderable::{BarTexture, OngybarState};
 use graphics::Transformed;
 use graphics::ImageSize;
 
-// This will be the Path string
-pub struct OngyImage(pub String);
+pub struct OngyImage {
+    /* A path, or the name of an icon in the icon theme */
+    pub path: String,
+    /* Explicit size in pixels. If only one is set, the other one keeps the
+     * aspect ratio. Without either the image is fit to the bar height */
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+}
+
+impl OngyImage {
+    pub fn new(path: String) -> Self {
+        return OngyImage { path: path, width: None, height: None };
+    }
+
+    /* The height the image is drawn at, as far as it's known before loading */
+    fn get_load_height(&self, height: u32, scale: f64) -> u32 {
+        return match self.height {
+            Some(h) => (h * scale).round() as u32,
+            None => height,
+        }
+    }
+}
+
+/* The size an image is drawn at. Explicit sizes are scaled, the bar height
+ * already is */
+fn get_dims<T: ImageSize>(width: Option<f64>, height: Option<f64>, i: &T,
+                          bar_height: u32, scale: f64) -> (f64, f64) {
+    let (i_width, i_height) = i.get_size();
+    if i_width == 0 || i_height == 0 {
+        return (0.0, 0.0);
+    }
+    let aspect = i_width as f64 / i_height as f64;
+
+    return match (width, height) {
+        (Some(w), Some(h)) => (w * scale, h * scale),
+        (Some(w), None) => (w * scale, w * scale / aspect),
+        (None, Some(h)) => (h * scale * aspect, h * scale),
+        (None, None) => (bar_height as f64 * aspect, bar_height as f64),
+    }
+}
+
+/* Draw i vertically centered at the size from get_dims. Returns the width */
+fn draw_image<G>(g: &mut G, i: &G::Texture, (width, i_height): (f64, f64), height: u32,
+                 trans: &graphics::math::Matrix2d, mask: Option<graphics::types::Color>) -> f64
+    where G: graphics::Graphics {
+    if width == 0.0 {
+        return 0.0;
+    }
+
+    let offset = height as f64 / 2.0 - i_height / 2.0;
+    let scale_x = width / i.get_width() as f64;
+    let scale_y = i_height / i.get_height() as f64;
+    let trans = trans.trans(0.0, offset).scale(scale_x, scale_y);
+    match mask {
+        Some(c) => graphics::Image::new_color(c).draw(i, &graphics::DrawState::default(), trans, g),
+        None => graphics::image(i, trans, g),
+    }
+    return width;
+}
 
 impl <G, C> Renderable<G, C> for OngyImage
-    where G: graphics::Graphics<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> {
-    fn get_size(&self, _: &mut C, _: u32, o: &mut OngybarState) -> f64 {
-        return match o.get_image(&self.0) {
-            Some(i) => i.get_width() as f64,
+    where G: graphics::Graphics,
+          G::Texture: BarTexture {
+    fn get_size(&self, _: &mut C, height: u32, o: &mut OngybarState<G::Texture>) -> f64 {
+        let scale = o.scale;
+        let load_height = self.get_load_height(height, scale);
+        let file = match o.resolve(&self.path, load_height) {
+            Some(x) => x,
+            None => return 0.0,
+        };
+
+        return match o.get_image(&file, load_height) {
+            Some(i) => get_dims(self.width, self.height, i, height, scale).0,
             None => 0.0,
         }
     }
 
     fn do_render(&self, g: &mut G, height: u32,
-                 o: &mut OngybarState, trans: &graphics::math::Matrix2d,
+                 o: &mut OngybarState<G::Texture>, trans: &graphics::math::Matrix2d,
+                 _: &mut C, c: graphics::types::Color) -> f64 {
+        let scale = o.scale;
+        let load_height = self.get_load_height(height, scale);
+        let file = match o.resolve(&self.path, load_height) {
+            Some(x) => x,
+            None => return 0.0,
+        };
+
+        return match o.get_image(&file, load_height) {
+            Some(i) => {
+                let dims = get_dims(self.width, self.height, i, height, scale);
+                /* Bitmaps only say where to draw, in the foreground colour like dzen does */
+                let mask = if images::is_mask(&file) { Some(c) } else { None };
+                draw_image(g, i, dims, height, trans, mask)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// An image the producer sent the pixels of, instead of a path
+pub struct OngyInlineImage {
+    /* Identifies the data, textures are shared between images with the same hash */
+    pub hash: u64,
+    pub data: images::InlineData,
+    /* Like for OngyImage */
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+}
+
+impl <G, C> Renderable<G, C> for OngyInlineImage
+    where G: graphics::Graphics,
+          G::Texture: BarTexture {
+    fn get_size(&self, _: &mut C, height: u32, o: &mut OngybarState<G::Texture>) -> f64 {
+        let scale = o.scale;
+        return match o.get_inline(self.hash, &self.data) {
+            Some(i) => get_dims(self.width, self.height, i, height, scale).0,
+            None => 0.0,
+        }
+    }
+
+    fn do_render(&self, g: &mut G, height: u32,
+                 o: &mut OngybarState<G::Texture>, trans: &graphics::math::Matrix2d,
                  _: &mut C, _: graphics::types::Color) -> f64 {
-        match o.get_image(&self.0) {
+        let scale = o.scale;
+        return match o.get_inline(self.hash, &self.data) {
             Some(i) => {
-                let i_height = i.get_height() as f64;
-                let offset = height as f64 / 2.0 - i_height / 2.0;
-                graphics::image(i, trans.trans(0.0, offset), g);
-                return i.get_width() as f64;
+                let dims = get_dims(self.width, self.height, i, height, scale);
+                draw_image(g, i, dims, height, trans, None)
             }
-            None => return 0.0,
+            None => 0.0,
         }
     }
 }
@@ -0,0 +1,36 @@
+extern crate graphics;
+
+use modules::renderable::{get_list_size, render_list, render_list_clipped};
+use modules::renderable::Renderable;
+use modules::renderable::OngybarState;
+
+/// A list nested in an output's content, e.g. what a dzen colour applies to
+pub struct OngyList<G, C>
+    where G: graphics::Graphics {
+    pub elems: Vec<Box<Renderable<G, C>>>,
+    /* Between the elements, in pixels before scaling. The same as the input's
+     * separator padding, so nested lists look like the top level */
+    pub spacing: f64,
+}
+
+impl<G, C> Renderable<G, C> for OngyList<G, C>
+    where G: graphics::Graphics {
+    fn get_size(&self, cache: &mut C, height: u32, o: &mut OngybarState<G::Texture>) -> f64 {
+        let spacing = self.spacing * o.scale;
+        return get_list_size(&self.elems, spacing, cache, height, o);
+    }
+
+    fn do_render(&self, g: &mut G, height: u32, o: &mut OngybarState<G::Texture>,
+                 trans: &graphics::math::Matrix2d, cache: &mut C,
+                 color: graphics::types::Color) -> f64 {
+        let spacing = self.spacing * o.scale;
+        return render_list(&self.elems, spacing, g, height, o, trans, cache, color);
+    }
+
+    fn do_render_clipped(&self, g: &mut G, height: u32, o: &mut OngybarState<G::Texture>,
+                         trans: &graphics::math::Matrix2d, cache: &mut C,
+                         color: graphics::types::Color, width: f64) -> f64 {
+        let spacing = self.spacing * o.scale;
+        return render_list_clipped(&self.elems, spacing, g, height, o, trans, cache, color, width);
+    }
+}
@@ -5,14 +5,15 @@ use modules::renderable::OngybarState;
 
 pub struct OngyPos(pub f64);
 
-impl<G, C> Renderable<G, C> for OngyPos {
-    fn get_size(&self, _: &mut C, _: u32, _: &mut OngybarState) -> f64
-    { return self.0; }
+impl<G, C> Renderable<G, C> for OngyPos
+    where G: graphics::Graphics {
+    fn get_size(&self, _: &mut C, _: u32, o: &mut OngybarState<G::Texture>) -> f64
+    { return self.0 * o.scale; }
 
-    fn do_render(&self, _: &mut G, _: u32, _: &mut OngybarState,
+    fn do_render(&self, _: &mut G, _: u32, o: &mut OngybarState<G::Texture>,
                  _: &graphics::math::Matrix2d, _: &mut C,
                  _: graphics::types::Color) -> f64 {
-        return self.0;
+        return self.0 * o.scale;
     }
 }
 
@@ -13,17 +13,19 @@ impl<G, C> Renderable<G, C> for OngyRect
     where C: graphics::character::CharacterCache,
           G: graphics::Graphics<Texture = <C as graphics::character::CharacterCache>::Texture> {
 
-    fn get_size(&self, _: &mut C, _: u32, _: &mut OngybarState) -> f64 {
-        return self.width;
+    fn get_size(&self, _: &mut C, _: u32, o: &mut OngybarState<G::Texture>) -> f64 {
+        return self.width * o.scale;
     }
 
-    fn do_render(&self, g: &mut G, height: u32, _: &mut OngybarState,
+    fn do_render(&self, g: &mut G, height: u32, o: &mut OngybarState<G::Texture>,
                  trans: &graphics::math::Matrix2d, _: &mut C,
                  c: graphics::types::Color) -> f64 {
         // This will be in pixels for now. Percent will probably go into another type
-        let dims = [0.0, height as f64 / 2.0 - self.height / 2.0, self.width, height as f64];
+        let width = self.width * o.scale;
+        let real_height = self.height * o.scale;
+        let dims = [0.0, height as f64 / 2.0 - real_height / 2.0, width, height as f64];
         graphics::rectangle(c, dims, trans.trans(0f64, 0f64), g);
-        return self.width;
+        return width;
     }
 }
 
@@ -36,11 +38,11 @@ impl<G, C> Renderable<G, C> for OngyRectR
     where C: graphics::character::CharacterCache,
           G: graphics::Graphics<Texture = <C as graphics::character::CharacterCache>::Texture> {
 
-    fn get_size(&self, _: &mut C, height: u32, _: &mut OngybarState) -> f64 {
+    fn get_size(&self, _: &mut C, height: u32, _: &mut OngybarState<G::Texture>) -> f64 {
         return self.width / 100.0 * height as f64;
     }
 
-    fn do_render(&self, g: &mut G, height: u32, _: &mut OngybarState,
+    fn do_render(&self, g: &mut G, height: u32, _: &mut OngybarState<G::Texture>,
                  trans: &graphics::math::Matrix2d, _: &mut C,
                  c: graphics::types::Color) -> f64 {
         // This will be in pixels for now. Percent will probably go into another type
@@ -4,26 +4,67 @@ use modules::renderable::Renderable;
 use modules::renderable::OngybarState;
 use graphics::Transformed;
 
+use std;
+
 pub struct OngyStr(pub String);
 
+/* In points, for font patterns without a size */
+const DEFAULT_FONT_SIZE: f64 = 10.0;
+
+/* The font size to draw at and where the baseline goes */
+fn get_metrics<T>(height: u32, o: &OngybarState<T>) -> (u32, f64) {
+    let size = match o.font_size {
+        Some(size) => (size * o.scale).round() as u32,
+        /* Shrunk on bars too thin for it */
+        None => std::cmp::min((DEFAULT_FONT_SIZE * o.scale).round() as u32, height * 2 / 3).max(1),
+    };
+
+    /* The ascent is about as many pixels as the font has points, centre that */
+    return (size, (height + size) as f64 / 2.0);
+}
+
 impl<G, C> Renderable<G, C> for OngyStr
     where C: graphics::character::CharacterCache,
           G: graphics::Graphics<Texture = <C as graphics::character::CharacterCache>::Texture> {
 
-    fn get_size(&self, cache: &mut C, height: u32, _: &mut OngybarState) -> f64 {
-        let text_height = (height - 2) * 2 / 3;
+    fn get_size(&self, cache: &mut C, height: u32, o: &mut OngybarState<G::Texture>) -> f64 {
+        let (text_height, _) = get_metrics(height, o);
 
         return cache.width(text_height, self.0.as_str());
     }
 
-    fn do_render(&self, g: &mut G, height: u32, _: &mut OngybarState,
+    fn do_render(&self, g: &mut G, height: u32, o: &mut OngybarState<G::Texture>,
                  trans: &graphics::math::Matrix2d, cache: &mut C,
                  c: graphics::types::Color) -> f64 {
-        let text_height = (height - 2) * 2 / 3;
+        let (text_height, baseline) = get_metrics(height, o);
         graphics::text(c, text_height, self.0.as_str(), cache,
-                       trans.trans(0f64, text_height as f64 + 2f64), g);
+                       trans.trans(0f64, baseline), g);
 
         return cache.width(text_height, self.0.as_str());
     }
+
+    fn do_render_clipped(&self, g: &mut G, height: u32, o: &mut OngybarState<G::Texture>,
+                         trans: &graphics::math::Matrix2d, cache: &mut C,
+                         c: graphics::types::Color, width: f64) -> f64 {
+        let (text_height, baseline) = get_metrics(height, o);
+        let mut used = 0.0;
+        let mut end = self.0.len();
+
+        /* Find the longest prefix that still fits, so we never cut a glyph */
+        for (i, ch) in self.0.char_indices() {
+            let mut buf = [0; 4];
+            let ch_width = cache.width(text_height, ch.encode_utf8(&mut buf));
+            if used + ch_width > width {
+                end = i;
+                break;
+            }
+            used += ch_width;
+        }
+
+        graphics::text(c, text_height, &self.0[..end], cache,
+                       trans.trans(0f64, baseline), g);
+
+        return used;
+    }
 }
 
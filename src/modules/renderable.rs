@@ -1,77 +1,332 @@
 extern crate graphics;
 extern crate opengl_graphics;
+extern crate texture;
+
+use config;
+use fonts;
+use icons::{IconLookup, RETRY_SECONDS};
+use images;
 
 use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 use std;
 use graphics::Transformed;
 
-pub struct OngybarState {
-    images: HashMap<String, <opengl_graphics::GlGraphics as graphics::Graphics>::Texture>,
+/// Textures the renderer can create from images and glyphs
+pub trait BarTexture: graphics::ImageSize + Sized {
+    fn from_path(path: &Path) -> Result<Self, String>;
+    /* data is RGBA, 8 bits per channel, without padding */
+    fn from_rgba(data: &[u8], width: u32, height: u32) -> Result<Self, String>;
+}
+
+impl BarTexture for opengl_graphics::Texture {
+    fn from_path(path: &Path) -> Result<Self, String> {
+        return opengl_graphics::Texture::from_path(path);
+    }
+
+    fn from_rgba(data: &[u8], width: u32, height: u32) -> Result<Self, String> {
+        let mut settings = texture::TextureSettings::new();
+        settings.set_filter(texture::Filter::Nearest);
+        return texture::CreateTexture::create(&mut (), texture::Format::Rgba8, data,
+                                              [width, height], &settings);
+    }
+}
+
+struct CachedImage<T> {
+    texture: T,
+    /* The file, the cache key may have the size in it as well. Empty for inline data */
+    path: String,
+    mtime: Option<SystemTime>,
+    /* The frame this was last used in. Changes are only looked for once per frame */
+    used: u64,
 }
 
-impl OngybarState {
-    pub fn new() -> Self {
-        return OngybarState { images: HashMap::new() };
+fn get_mtime(path: &str) -> Option<SystemTime> {
+    return std::fs::metadata(path).and_then(|x| x.modified()).ok();
+}
+
+pub struct OngybarState<T> {
+    images: HashMap<String, CachedImage<T>>,
+    /* When loading failed, and the mtime the file had then */
+    failed: HashMap<String, (Instant, Option<SystemTime>)>,
+    icons: IconLookup,
+    /* Counts up every time all bars got drawn */
+    frame: u64,
+    /* Images unused for longer than this are dropped */
+    max_unused: u64,
+    /* In bytes, least recently used images are dropped beyond this */
+    budget: usize,
+    /* The HiDPI scale of the monitor currently drawn on. Pixel sizes are multiplied by this */
+    pub scale: f64,
+    /* In points, from the font pattern. Without it a default size is used */
+    pub font_size: Option<f64>,
+}
+
+impl<T: BarTexture> OngybarState<T> {
+    pub fn new(conf: &config::Config) -> Self {
+        return OngybarState { images: HashMap::new(), failed: HashMap::new(),
+                              icons: IconLookup::new(&conf.icon_theme),
+                              frame: 0,
+                              max_unused: conf.image_cache_frames.max(0) as u64,
+                              budget: conf.image_cache_size.max(0) as usize * 1024,
+                              scale: 1.0,
+                              font_size: fonts::get_size(&conf.font) };
+    }
+
+    /// Get the file for an image. Names without a '/' that aren't files in
+    /// the working directory are looked up as icons, in the size closest to height
+    pub fn resolve(&mut self, name: &String, height: u32) -> Option<String> {
+        if name.contains('/') || Path::new(name).is_file() {
+            return Some(name.clone());
+        }
+
+        match self.icons.find(name, height) {
+            Some(x) => return Some(x),
+            None => {
+                /* Most likely a relative path to a file that isn't there (yet).
+                 * Loading it reports that, and tries again later */
+                if Path::new(name).extension().is_some() {
+                    return Some(name.clone());
+                }
+                return None;
+            },
+        }
     }
 
-    pub fn get_image(&mut self, path: &String) -> Option<&opengl_graphics::Texture> {
-        /* Load the image, insert it into the cache, and then get it */
-        if !self.images.contains_key(path) {
-            match  opengl_graphics::Texture::from_path(path) {
-                Ok(image) =>  {
-                    self.images.insert(path.clone(), image);
+    /// Get the image at path from the cache, or load it.
+    /// height is the size scalable images are rasterized at
+    pub fn get_image(&mut self, path: &String, height: u32) -> Option<&T> {
+        /* Scalable images are cached for every size they are drawn at */
+        let key = if images::is_scalable(path) {
+            format!("{}@{}", path, height)
+        } else {
+            path.clone()
+        };
+        let frame = self.frame;
+
+        let stale = match self.images.get_mut(&key) {
+            Some(ref mut image) if image.used != frame => {
+                image.used = frame;
+                /* A file that's gone may be replaced in a moment, keep what we have */
+                match get_mtime(&image.path) {
+                    Some(x) => Some(x) != image.mtime,
+                    None => false,
+                }
+            },
+            Some(_) => false,
+            None => true,
+        };
+
+        if stale {
+            let mtime = get_mtime(path);
+            let retry = match self.failed.get(&key) {
+                Some(&(when, failed_mtime)) =>
+                    failed_mtime != mtime || when.elapsed() >= Duration::from_secs(RETRY_SECONDS),
+                None => true,
+            };
+
+            if retry {
+                /* If this fails, whatever was loaded before stays */
+                match images::load(path, height) {
+                    Ok(texture) => {
+                        self.failed.remove(&key);
+                        self.images.insert(key.clone(), CachedImage { texture: texture, path: path.clone(),
+                                                                      mtime: mtime, used: frame });
+                    },
+                    Err(x) => {
+                        if !self.failed.contains_key(&key) {
+                            println!("Couldn't load image {}: {}", path, x);
+                        }
+                        self.failed.insert(key.clone(), (Instant::now(), mtime));
+                    },
                 }
-                Err(_) => return None
             }
         }
 
-        return self.images.get(path);
+        return self.images.get(&key).map(|x| &x.texture);
+    }
+
+    /// Get the texture for image data a producer sent inline.
+    /// hash identifies the data, so the same image is only uploaded once
+    pub fn get_inline(&mut self, hash: u64, data: &images::InlineData) -> Option<&T> {
+        let key = format!("inline:{:016x}", hash);
+        let frame = self.frame;
+
+        let missing = match self.images.get_mut(&key) {
+            Some(image) => {
+                image.used = frame;
+                false
+            },
+            None => true,
+        };
+
+        if missing && !self.failed.contains_key(&key) {
+            /* The data can't change without the hash changing, no point in retrying */
+            match images::load_inline(data) {
+                Ok(texture) => {
+                    self.images.insert(key.clone(), CachedImage { texture: texture, path: String::new(),
+                                                                  mtime: None, used: frame });
+                },
+                Err(x) => {
+                    println!("Couldn't load inline image {:016x}: {}", hash, x);
+                    self.failed.insert(key.clone(), (Instant::now(), None));
+                },
+            }
+        }
+
+        return self.images.get(&key).map(|x| &x.texture);
+    }
+
+    /// Call once all bars got drawn, this drops images that aren't used anymore.
+    /// Not after every bar, or images only drawn on other bars would look unused
+    pub fn end_frame(&mut self) {
+        let frame = self.frame;
+        let max_unused = self.max_unused;
+        self.images.retain(|_, image| frame - image.used <= max_unused);
+
+        let size = |image: &CachedImage<T>| {
+            let (width, height) = image.texture.get_size();
+            width as usize * height as usize * 4
+        };
+        let mut total: usize = self.images.values().map(&size).sum();
+
+        if total > self.budget {
+            /* Oldest first. What was just drawn stays, or it would be loaded again right away */
+            let mut old: Vec<(u64, String, usize)> = self.images.iter()
+                .filter(|&(_, image)| image.used != frame)
+                .map(|(key, image)| (image.used, key.clone(), size(image))).collect();
+            old.sort();
+
+            for (_, key, bytes) in old {
+                if total <= self.budget {
+                    break;
+                }
+                self.images.remove(&key);
+                total -= bytes;
+            }
+        }
+
+        /* Failures are refreshed whenever they're retried, so these aren't drawn anymore */
+        self.failed.retain(|_, &mut (when, _)| when.elapsed() < Duration::from_secs(RETRY_SECONDS * 60));
+
+        self.frame += 1;
     }
 }
 
 
-pub trait Renderable<G, C> {
-    fn get_size(&self, cache: &mut C, height: u32, &mut OngybarState) -> f64;
-    fn do_render(&self, g: &mut G, height: u32, o: &mut OngybarState,
+pub trait Renderable<G, C>
+    where G: graphics::Graphics {
+    fn get_size(&self, cache: &mut C, height: u32, &mut OngybarState<G::Texture>) -> f64;
+    fn do_render(&self, g: &mut G, height: u32, o: &mut OngybarState<G::Texture>,
                  trans: &graphics::math::Matrix2d, cache: &mut C,
                  color: graphics::types::Color) -> f64;
+
+    /// The colour filling the full height behind the element, if any.
+    ///
+    /// Lists extend it over the space to the next element, so neighbouring
+    /// backgrounds don't have gaps between them.
+    fn get_background(&self) -> Option<graphics::types::Color> {
+        return None;
+    }
+
+    /// Render into at most `width` pixels and return how much was used.
+    ///
+    /// By default the element is drawn as usual and the caller's viewport has
+    /// to clip the rest. Text overrides this to stop at a glyph boundary.
+    fn do_render_clipped(&self, g: &mut G, height: u32, o: &mut OngybarState<G::Texture>,
+                         trans: &graphics::math::Matrix2d, cache: &mut C,
+                         color: graphics::types::Color, width: f64) -> f64 {
+        let size = self.do_render(g, height, o, trans, cache, color);
+        return size.min(width);
+    }
 }
 
-impl<G, C, I> Renderable<G, C> for I
-    where for<'a> &'a I: std::iter::IntoIterator<Item=&'a Box<Renderable<G, C>>> {
-    fn get_size(&self, c: &mut C, h: u32, o: &mut OngybarState) -> f64 {
-        let mut first = true;
-        let mut ret = 0f64;
-
-        for ref x in self.into_iter() {
-            if first {
-                first = false;
-            } else {
-                ret += 4f64;
-            }
+/// Get the width of list, with spacing between its elements
+pub fn get_list_size<G, C, I>(list: &I, spacing: f64, c: &mut C, h: u32,
+                              o: &mut OngybarState<G::Texture>) -> f64
+    where G: graphics::Graphics,
+          for<'a> &'a I: std::iter::IntoIterator<Item=&'a Box<Renderable<G, C>>> {
+    let mut first = true;
+    let mut ret = 0f64;
 
-            ret += x.get_size(c, h, o);
+    for ref x in list.into_iter() {
+        if first {
+            first = false;
+        } else {
+            ret += spacing;
         }
-        return ret;
+
+        ret += x.get_size(c, h, o);
     }
+    return ret;
+}
 
-    fn do_render(&self, g: &mut G, h: u32, o: &mut OngybarState,
-                 trans: &graphics::math::Matrix2d, cache: &mut C,
-                 c: graphics::types::Color) -> f64 {
-        let mut first = true;
-        let mut total_offset = 0f64;
-        let mut cur_trans = trans.trans(0f64, 0f64);
+/// Draw list with spacing between its elements
+pub fn render_list<G, C, I>(list: &I, spacing: f64, g: &mut G, h: u32,
+                            o: &mut OngybarState<G::Texture>,
+                            trans: &graphics::math::Matrix2d, cache: &mut C,
+                            c: graphics::types::Color) -> f64
+    where G: graphics::Graphics,
+          for<'a> &'a I: std::iter::IntoIterator<Item=&'a Box<Renderable<G, C>>> {
+    let mut first = true;
+    let mut total_offset = 0f64;
+    let mut cur_trans = trans.trans(0f64, 0f64);
+    let mut iter = list.into_iter().peekable();
+
+    while let Some(x) = iter.next() {
+        let offset = x.do_render(g, h, o, &cur_trans, cache, c);
+        if let (Some(bg), Some(_)) = (x.get_background(), iter.peek()) {
+            graphics::rectangle(bg, [offset, 0.0, spacing, h as f64], cur_trans, g);
+        }
+        cur_trans = cur_trans.trans(offset + spacing, 0f64);
+        total_offset += offset;
+        if !first {
+            total_offset += spacing;
+        } else {
+            first = false;
+        }
+    }
+    return total_offset;
+}
 
-        for ref x in self.into_iter() {
+/// Draw list with spacing between its elements into at most width pixels
+pub fn render_list_clipped<G, C, I>(list: &I, spacing: f64, g: &mut G, h: u32,
+                                    o: &mut OngybarState<G::Texture>,
+                                    trans: &graphics::math::Matrix2d, cache: &mut C,
+                                    c: graphics::types::Color, width: f64) -> f64
+    where G: graphics::Graphics,
+          for<'a> &'a I: std::iter::IntoIterator<Item=&'a Box<Renderable<G, C>>> {
+    let mut first = true;
+    let mut total_offset = 0f64;
+    let mut iter = list.into_iter().peekable();
+
+    while let Some(x) = iter.next() {
+        if !first {
+            total_offset += spacing;
+        } else {
+            first = false;
+        }
+
+        let remaining = width - total_offset;
+        if remaining <= 0.0 {
+            break;
+        }
+
+        let cur_trans = trans.trans(total_offset, 0f64);
+        /* Everything that fits is drawn as usual, the first thing that
+         * doesn't gets clipped and ends the list */
+        if x.get_size(cache, h, o) <= remaining {
             let offset = x.do_render(g, h, o, &cur_trans, cache, c);
-            cur_trans = cur_trans.trans(offset + 4f64, 0f64);
-            total_offset += offset;
-            if !first {
-                total_offset += 4.0;
-            } else {
-                first = false;
+            if let (Some(bg), Some(_)) = (x.get_background(), iter.peek()) {
+                let gap = spacing.min(remaining - offset).max(0.0);
+                graphics::rectangle(bg, [offset, 0.0, gap, h as f64], cur_trans, g);
             }
+            total_offset += offset;
+        } else {
+            total_offset += x.do_render_clipped(g, h, o, &cur_trans, cache, c, remaining);
+            break;
         }
-        return total_offset;
     }
+    return total_offset.min(width);
 }
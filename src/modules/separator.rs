@@ -1,23 +1,64 @@
 extern crate graphics;
 
+use ::config;
 use modules::renderable::Renderable;
 use modules::renderable::OngybarState;
-use graphics::Transformed;
+use modules::ongystr::OngyStr;
 
-pub struct Separator;
+pub enum SeparatorKind {
+    Line,
+    Gap,
+    Text(OngyStr),
+}
+
+pub struct Separator {
+    pub kind: SeparatorKind,
+    pub width: config::Size,
+    pub color: Option<graphics::types::Color>,
+}
+
+impl Separator {
+    /// Get the separator described by the config. None if it shouldn't be drawn at all
+    pub fn from_config(conf: &config::Separator) -> Option<Self> {
+        let kind = match &conf.style {
+            &config::SeparatorStyle::Nothing => return None,
+            &config::SeparatorStyle::Line => SeparatorKind::Line,
+            &config::SeparatorStyle::Gap => SeparatorKind::Gap,
+            &config::SeparatorStyle::Text(ref x) => SeparatorKind::Text(OngyStr(x.clone())),
+        };
+
+        return Some(Separator { kind: kind, width: conf.width.clone(),
+                                color: conf.color.as_ref().map(|c| c.to_graphics()) });
+    }
+}
 
 impl<G, C> Renderable<G, C> for Separator
-    where G: graphics::Graphics {
+    where C: graphics::character::CharacterCache,
+          G: graphics::Graphics<Texture = <C as graphics::character::CharacterCache>::Texture> {
 
-    fn get_size(&self, _: &mut C, _: u32, _: &mut OngybarState) -> f64
-    { return 1f64; }
+    fn get_size(&self, cache: &mut C, height: u32, o: &mut OngybarState<G::Texture>) -> f64 {
+        return match &self.kind {
+            &SeparatorKind::Text(ref x) => Renderable::<G, C>::get_size(x, cache, height, o),
+            _ => self.width.get_height(height as i32, o.scale) as f64,
+        }
+    }
 
-    fn do_render(&self, g: &mut G, height: u32, _: &mut OngybarState,
-                 trans: &graphics::math::Matrix2d, _: &mut C,
+    fn do_render(&self, g: &mut G, height: u32, o: &mut OngybarState<G::Texture>,
+                 trans: &graphics::math::Matrix2d, cache: &mut C,
                  c: graphics::types::Color) -> f64 {
-        graphics::line(c, 0.5f64, [0f64, 3f64, 0f64, height as f64 - 3f64],
-                       trans.trans(0f64, 0f64), g);
-
-        return 1f64;
+        let c = self.color.unwrap_or(c);
+        match &self.kind {
+            &SeparatorKind::Line => {
+                let width = self.width.get_height(height as i32, o.scale) as f64;
+                graphics::rectangle(c, [0f64, 3f64, width, height as f64 - 6f64], *trans, g);
+                return width;
+            },
+            &SeparatorKind::Gap => {
+                return self.width.get_height(height as i32, o.scale) as f64;
+            },
+            &SeparatorKind::Text(ref x) => {
+                return x.do_render(g, height, o, trans, cache, c);
+            },
+        }
     }
 }
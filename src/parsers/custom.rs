@@ -3,25 +3,30 @@
 // TODO: Do this :)
 
 extern crate graphics;
-extern crate opengl_graphics;
 extern crate num_traits;
 extern crate byteorder;
 
 use self::byteorder::ReadBytesExt;
 
-use modules::renderable::Renderable;
+use modules::renderable::{BarTexture, Renderable};
 use modules::ongystr::OngyStr;
-use modules::ongyimage::OngyImage;
+use modules::ongyimage::{OngyImage, OngyInlineImage};
 use modules::ongyrect::OngyRectR;
 use modules::colored::Colored;
+use modules::ongylist::OngyList;
 use modules::ongydraw::*;
 
+use images::{InlineData, MAX_INLINE_BYTES};
+
 use std;
+use std::io::Read;
 use std::vec::Vec;
 
 struct CustomIter<'a, G, C, R: 'a> {
     r: &'a mut R,
     num: Option<u8>,
+    /* For nested lists */
+    spacing: f64,
     g: std::marker::PhantomData<G>,
     c: std::marker::PhantomData<C>,
 }
@@ -29,18 +34,21 @@ struct CustomIter<'a, G, C, R: 'a> {
 struct DrawIter<'a, R: 'a> {
     r: &'a mut R,
     num: u8,
+    /* The f32 command set of DrawF instead of the u16 one */
+    float: bool,
 }
 
 impl<'a, R> DrawIter<'a, R> {
-    fn new(read: &'a mut R, num: u8) -> Self {
-        DrawIter {r: read, num: num}
+    fn new(read: &'a mut R, num: u8, float: bool) -> Self {
+        DrawIter {r: read, num: num, float: float}
     }
 }
 
 impl<'a, G, C, R> CustomIter<'a, G, C, R> {
-    fn new(reader: &'a mut R) -> Self {
+    fn new(reader: &'a mut R, spacing: f64) -> Self {
         return CustomIter{ r: reader,
                            num: None,
+                           spacing: spacing,
                            g: std::marker::PhantomData,
                            c: std::marker::PhantomData  };
     }
@@ -69,7 +77,94 @@ fn parse_text<R> (r: &mut R) -> OngyStr
 fn parse_image<R> (r: &mut R) -> OngyImage
     where R: std::io::Read {
     let OngyStr(x) = parse_text(r);
-    return OngyImage(x);
+    return OngyImage::new(x);
+}
+
+fn parse_sized_image<R> (r: &mut R) -> OngyImage
+    where R: std::io::Read {
+    let width = r.read_u16::<byteorder::NativeEndian>().unwrap();
+    let height = r.read_u16::<byteorder::NativeEndian>().unwrap();
+    let OngyStr(x) = parse_text(r);
+
+    /* 0 keeps the aspect ratio */
+    let size = |x: u16| if x == 0 { None } else { Some(x as f64) };
+    return OngyImage { path: x, width: size(width), height: size(height) };
+}
+
+/* Read over data that isn't used, so the next element can still be parsed.
+ * Err if the input ends first */
+fn skip_data<R> (r: &mut R, len: usize) -> Result<(), ()>
+    where R: std::io::Read {
+    let skipped = std::io::copy(&mut r.take(len as u64), &mut std::io::sink()).unwrap();
+    if (skipped as usize) < len {
+        println!("Input ended in the middle of an inline image");
+        return Err(());
+    }
+
+    return Ok(());
+}
+
+/* The length comes from the stream, so the buffer only grows as data arrives.
+ * Data that is too large is skipped and None returned for it */
+fn parse_data<R> (r: &mut R, len: usize) -> Result<Option<Vec<u8>>, ()>
+    where R: std::io::Read {
+    if len > MAX_INLINE_BYTES {
+        println!("Inline image data of {} bytes is too large", len);
+        return skip_data(r, len).map(|_| None);
+    }
+
+    let mut data = Vec::new();
+    r.take(len as u64).read_to_end(&mut data).unwrap();
+    if data.len() < len {
+        println!("Input ended in the middle of an inline image");
+        return Err(());
+    }
+
+    return Ok(Some(data));
+}
+
+/* None if the image was dropped, but read completely. Err if the rest of the
+ * stream can't be read */
+fn parse_inline_image<R> (r: &mut R) -> Result<Option<OngyInlineImage>, ()>
+    where R: std::io::Read {
+    let width = r.read_u16::<byteorder::NativeEndian>().unwrap();
+    let height = r.read_u16::<byteorder::NativeEndian>().unwrap();
+    let hash = r.read_u64::<byteorder::NativeEndian>().unwrap();
+    let encoding = r.read_u8().unwrap();
+
+    let data = match encoding {
+        0 => {
+            let i_width = r.read_u16::<byteorder::NativeEndian>().unwrap();
+            let i_height = r.read_u16::<byteorder::NativeEndian>().unwrap();
+            let stride = r.read_u32::<byteorder::NativeEndian>().unwrap();
+            let len = stride as usize * i_height as usize;
+            if (stride as usize) < i_width as usize * 4 {
+                println!("Inline image is {} pixels wide, but has a stride of {}", i_width, stride);
+                return skip_data(r, len).map(|_| None);
+            }
+
+            match parse_data(r, len)? {
+                Some(x) => InlineData::Rgba { width: i_width as u32, height: i_height as u32, stride: stride, data: x },
+                None => return Ok(None),
+            }
+        },
+        1 => {
+            let len = r.read_u32::<byteorder::NativeEndian>().unwrap();
+            match parse_data(r, len as usize)? {
+                Some(x) => InlineData::Png(x),
+                None => return Ok(None),
+            }
+        },
+        x => {
+            /* The length of the data depends on this, so the rest of the stream can't be read */
+            println!("Found an image encoding I couldn't interpret while parsing custom format: {}", x);
+            return Err(());
+        },
+    };
+
+    /* 0 keeps the aspect ratio */
+    let size = |x: u16| if x == 0 { None } else { Some(x as f64) };
+    return Ok(Some(OngyInlineImage { hash: hash, data: data, width: size(width), height: size(height) }));
 }
 
 fn parse_colorfrag<R> (r: &mut R) -> graphics::types::Color
@@ -84,19 +179,23 @@ fn parse_colorfrag<R> (r: &mut R) -> graphics::types::Color
             ret[3] as f32 / 255.0];
 }
 
-fn parse_color<R, G, C> (r: &mut R) -> Colored<G, C>
-    where C: graphics::character::CharacterCache<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static,
-          G: graphics::Graphics<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static,
+fn parse_color<R, G, C> (r: &mut R, spacing: f64) -> Result<Colored<G, C>, ()>
+    where C: graphics::character::CharacterCache<Texture = <G as graphics::Graphics>::Texture> + 'static,
+          G: graphics::Graphics + 'static,
+          <G as graphics::Graphics>::Texture: BarTexture,
           R: std::io::Read {
     let fg = parse_colorfrag(r);
-    /* We don't do anything for background currently, ignore this */
-    let _ = parse_colorfrag(r);
+    let bg = parse_colorfrag(r);
+    /* A fully transparent background is what producers send when they don't
+     * want one, so don't bother drawing it */
+    let background = if bg[3] > 0.0 { Some(bg) } else { None };
 
-    match parse_elem(r) {
-        Some(x) => Colored { color: fg, elem: x },
+    match parse_elem(r, spacing)? {
+        Some(x) => return Ok(Colored { color: Some(fg), background: background, elem: x }),
         None => {
             println!("Couldn't decode child element of color in custom format :(");
-            return Colored { color: fg, elem: Box::new(OngyStr(String::from("ERR"))) };
+            return Ok(Colored { color: Some(fg), background: background,
+                                elem: Box::new(OngyStr(String::from("ERR"))) });
         }
     }
 }
@@ -109,40 +208,172 @@ fn parse_rect<R> (r: &mut R) -> OngyRectR
     return OngyRectR { width: width as f64, height: height as f64 }
 }
 
-fn parse_draw_elem<R> (r: &mut R) -> DrawCommand
+/* A coordinate or size in a draw command */
+fn parse_num<R> (r: &mut R, float: bool) -> f64
+    where R: std::io::Read {
+    if float {
+        return r.read_f32::<byteorder::NativeEndian>().unwrap() as f64;
+    }
+
+    return r.read_u16::<byteorder::NativeEndian>().unwrap() as f64;
+}
+
+/* A u8 count, followed by that many x/y pairs */
+fn parse_points<R> (r: &mut R, float: bool) -> Vec<(f64, f64)>
+    where R: std::io::Read {
+    let num = r.read_u8().unwrap();
+
+    return (0..num).map(|_| {
+        let x = parse_num(r, float);
+        let y = parse_num(r, float);
+        (x, y)
+    }).collect();
+}
+
+/* A u8 count, followed by that many segments */
+fn parse_path<R> (r: &mut R, float: bool) -> Option<Vec<PathSegment>>
+    where R: std::io::Read {
+    let num = r.read_u8().unwrap();
+    let mut ret = Vec::new();
+
+    for _ in 0..num {
+        match r.read_u8().unwrap() {
+            0 => {
+                let x = parse_num(r, float);
+                let y = parse_num(r, float);
+                ret.push(PathSegment::Move(x, y));
+            },
+            1 => {
+                let x = parse_num(r, float);
+                let y = parse_num(r, float);
+                ret.push(PathSegment::Line(x, y));
+            },
+            2 => {
+                let x1 = parse_num(r, float);
+                let y1 = parse_num(r, float);
+                let x2 = parse_num(r, float);
+                let y2 = parse_num(r, float);
+                let x = parse_num(r, float);
+                let y = parse_num(r, float);
+                ret.push(PathSegment::Curve(x1, y1, x2, y2, x, y));
+            },
+            3 => ret.push(PathSegment::Close),
+            x => {
+                /* Can't know how long the arguments are, so the rest of the stream can't be read */
+                println!("Found a path segment I can't work with :( {}", x);
+                return None;
+            },
+        }
+    }
+
+    return Some(ret);
+}
+
+fn parse_coordtype(val: u8) -> Coordtype {
+    return match val {
+        0 => Coordtype::Absolute,
+        1 => Coordtype::Relative,
+        2 => Coordtype::SemiRelative,
+        x => {
+                println!("Found unused coord type while parsing OngyDraw: {}", x);
+                println!("Defaulting to relative");
+                Coordtype::Relative
+            },
+    };
+}
+
+/* None if the command couldn't be read completely */
+fn parse_draw_elem<R> (r: &mut R, float: bool) -> Option<DrawCommand>
     where R: std::io::Read {
 
     let mut type_enum = [0xff;1];
     r.read(&mut type_enum).unwrap();
-    match type_enum[0] {
+
+    /* The f32 set has the coordinate type per command, 0xff uses the element's */
+    if float {
+        let coords = r.read_u8().unwrap();
+        if coords != 0xff {
+            let val = parse_draw_cmd(r, type_enum[0], true);
+            return val.map(|x| DrawCommand::Coords(DrawCoords::new(x, parse_coordtype(coords))));
+        }
+    }
+
+    return parse_draw_cmd(r, type_enum[0], float);
+}
+
+fn parse_draw_cmd<R> (r: &mut R, type_enum: u8, float: bool) -> Option<DrawCommand>
+    where R: std::io::Read {
+    match type_enum {
         1 => { /* 1 is the Rectangle */
-            let x1 = r.read_u16::<byteorder::NativeEndian>().unwrap();
-            let y1 = r.read_u16::<byteorder::NativeEndian>().unwrap();
-            let x2 = r.read_u16::<byteorder::NativeEndian>().unwrap();
-            let y2 = r.read_u16::<byteorder::NativeEndian>().unwrap();
-
-            return DrawCommand::Rect(
-                    DrawRect::new(
-                        x1 as f64,
-                        y1 as f64,
-                        x2 as f64,
-                        y2 as f64));
+            let x1 = parse_num(r, float);
+            let y1 = parse_num(r, float);
+            let x2 = parse_num(r, float);
+            let y2 = parse_num(r, float);
+
+            return Some(DrawCommand::Rect(DrawRect::new(x1, y1, x2, y2)));
+        }
+        2 => { /* 2 is a line */
+            let x1 = parse_num(r, float);
+            let y1 = parse_num(r, float);
+            let x2 = parse_num(r, float);
+            let y2 = parse_num(r, float);
+            let width = parse_num(r, float);
+
+            return Some(DrawCommand::Line(DrawLine::new(x1, y1, x2, y2, width)));
         }
         3 => { /* 3 is a coloured element */
             let color = parse_colorfrag(r);
-            let val = parse_draw_elem(r);
-            return DrawCommand::Col(DrawCol::new(val, color));
+            let val = parse_draw_elem(r, float);
+            return val.map(|x| DrawCommand::Col(DrawCol::new(x, color)));
+        }
+        4 => { /* 4 is a circle */
+            let x = parse_num(r, float);
+            let y = parse_num(r, float);
+            let radius = parse_num(r, float);
+            let width = parse_num(r, float);
+
+            return Some(DrawCommand::Circle(DrawCircle::new(x, y, radius, width)));
+        }
+        5 => { /* 5 is an arc */
+            let x = parse_num(r, float);
+            let y = parse_num(r, float);
+            let radius = parse_num(r, float);
+            let start = parse_num(r, float);
+            let end = parse_num(r, float);
+            let width = parse_num(r, float);
+
+            return Some(DrawCommand::Arc(DrawArc::new(x, y, radius, start, end, width)));
+        }
+        6 => { /* 6 is a filled polygon */
+            let points = parse_points(r, float);
+            return Some(DrawCommand::Polygon(DrawPolygon::new(points, 0.0)));
+        }
+        7 => { /* 7 is the outline of a polygon */
+            let width = parse_num(r, float);
+            let points = parse_points(r, float);
+            return Some(DrawCommand::Polygon(DrawPolygon::new(points, width)));
+        }
+        8 => { /* 8 is a polyline */
+            let width = parse_num(r, float);
+            let points = parse_points(r, float);
+            return Some(DrawCommand::Polyline(DrawPolyline::new(points, width)));
+        }
+        9 => { /* 9 is a path */
+            let width = parse_num(r, float);
+            let segments = parse_path(r, float);
+            return segments.map(|x| DrawCommand::Path(DrawPath::new(x, width)));
         }
         x => {
+            /* Same as with paths, the arguments can't be skipped */
             println!("Found something I can't work with :( {}", x);
-            return DrawCommand::Rect(DrawRect::new(0.0, 0.0, 0.0, 0.0));
+            return None;
         }
     }
 }
 
 impl<'a, R> Iterator for DrawIter<'a, R>
     where R: std::io::Read {
-    type Item=DrawCommand;
+    type Item=Option<DrawCommand>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.num < 1 {
@@ -150,55 +381,76 @@ impl<'a, R> Iterator for DrawIter<'a, R>
         }
         self.num = self.num - 1;
 
-        return Some(parse_draw_elem(self.r));
+        return Some(parse_draw_elem(self.r, self.float));
     }
 }
 
-fn parse_draw<R> (r: &mut R) -> OngyDraw
+fn parse_draw<R> (r: &mut R) -> Option<OngyDraw>
     where R: std::io::Read {
     let mut buffer = [1];
     r.read(&mut buffer).unwrap();
 
-    let coords = match buffer[0] {
-        0 => Coordtype::Absolute,
-        1 => Coordtype::Relative,
-        2 => Coordtype::SemiRelative,
-        x => {
-                println!("Found unused coord type while parsing OngyDraw: {}", x);
-                println!("Defaulting to relative");
-                Coordtype::Relative
-            },
-    };
+    let coords = parse_coordtype(buffer[0]);
 
     r.read(&mut buffer).unwrap();
 
-    return OngyDraw::new(coords, DrawIter::new(r, buffer[0]));
+    let values: Option<Vec<DrawCommand>> = DrawIter::new(r, buffer[0], false).collect();
+    return values.map(|x| OngyDraw::new(coords, DrawWidth::Sum, x.into_iter()));
+}
+
+fn parse_draw_width<R> (r: &mut R) -> DrawWidth
+    where R: std::io::Read {
+    /* Negative means whatever the commands cover. NaN and infinity can't
+     * be a width either, so they are treated the same */
+    let width = r.read_f32::<byteorder::NativeEndian>().unwrap();
+    if width < 0.0 || !width.is_finite() {
+        return DrawWidth::Extents;
+    }
+
+    return DrawWidth::Fixed(width as f64);
+}
+
+fn parse_draw_float<R> (r: &mut R) -> Option<OngyDraw>
+    where R: std::io::Read {
+    let coords = parse_coordtype(r.read_u8().unwrap());
+    let width = parse_draw_width(r);
+    let num = r.read_u8().unwrap();
+
+    let values: Option<Vec<DrawCommand>> = DrawIter::new(r, num, true).collect();
+    return values.map(|x| OngyDraw::new(coords, width, x.into_iter()));
 }
 
-fn parse_elem<G, C, R> (r: &mut R) -> Option<Box<Renderable<G, C>>>
-    where C: graphics::character::CharacterCache<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static,
-          G: graphics::Graphics<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static,
+/* None if the element was dropped, but the stream is still usable.
+ * Err if the rest of the stream can't be made sense of */
+fn parse_elem<G, C, R> (r: &mut R, spacing: f64) -> Result<Option<Box<Renderable<G, C>>>, ()>
+    where C: graphics::character::CharacterCache<Texture = <G as graphics::Graphics>::Texture> + 'static,
+          G: graphics::Graphics + 'static,
+          <G as graphics::Graphics>::Texture: BarTexture,
           R: std::io::Read {
     /* Ok, we will read one element at a time. So we first read in the type enum value */
     let mut type_enum = [0;1];
     r.read(&mut type_enum).unwrap();
     match type_enum[0] {
-        0 => return Some(Box::new(custom_parse(r))),
-        1 => return Some(Box::new(parse_text(r))),
-        2 => return Some(Box::new(parse_image(r))),
-        3 => return Some(Box::new(parse_color(r))),
-        4 => return Some(Box::new(parse_rect(r))),
-        5 => return Some(Box::new(parse_draw(r))),
+        0 => return Ok(Some(Box::new(OngyList { elems: custom_parse(r, spacing), spacing: spacing }))),
+        1 => return Ok(Some(Box::new(parse_text(r)))),
+        2 => return Ok(Some(Box::new(parse_image(r)))),
+        3 => return Ok(Some(Box::new(parse_color(r, spacing)?))),
+        4 => return Ok(Some(Box::new(parse_rect(r)))),
+        5 => return parse_draw(r).map(|x| Some(Box::new(x) as Box<Renderable<G, C>>)).ok_or(()),
+        6 => return parse_draw_float(r).map(|x| Some(Box::new(x) as Box<Renderable<G, C>>)).ok_or(()),
+        7 => return Ok(Some(Box::new(parse_sized_image(r)))),
+        8 => return parse_inline_image(r).map(|x| x.map(|x| Box::new(x) as Box<Renderable<G, C>>)),
         x => {
             println!("Found a type I couldn't interpret while parsing custom format: {}", x);
-            return None
+            return Err(());
         },
     }
 }
 
 impl<'a, G, C, R> Iterator for CustomIter<'a, G, C, R>
-    where C: graphics::character::CharacterCache<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static,
-          G: graphics::Graphics<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static,
+    where C: graphics::character::CharacterCache<Texture = <G as graphics::Graphics>::Texture> + 'static,
+          G: graphics::Graphics + 'static,
+          <G as graphics::Graphics>::Texture: BarTexture,
           R: std::io::Read {
     type Item=Box<Renderable<G, C>>;
 
@@ -211,24 +463,237 @@ impl<'a, G, C, R> Iterator for CustomIter<'a, G, C, R>
             self.num = Some(buffer[0]);
         }
 
-        /* We are guaranteed to have *something* in here, so we just unwrap the value */
-        let remaining = self.num.unwrap();
-        if remaining < 1 {
-            /* We read all elements of the list. End now */
-            return None;
-        } else {
-            /* We will read another element from the list, so we decrement by 1 */
-            self.num = Some(remaining - 1);
+        loop {
+            /* We are guaranteed to have *something* in here, so we just unwrap the value */
+            let remaining = self.num.unwrap();
+            if remaining < 1 {
+                /* We read all elements of the list. End now */
+                return None;
+            } else {
+                /* We will read another element from the list, so we decrement by 1 */
+                self.num = Some(remaining - 1);
+            }
+
+            match parse_elem(self.r, self.spacing) {
+                Ok(Some(x)) => return Some(x),
+                /* Dropped, go on with the next one */
+                Ok(None) => {},
+                Err(_) => return None,
+            }
         }
-
-        return parse_elem(self.r);
     }
 }
 
-/* This should be called with buffered reader for performance reasons! */
-pub fn custom_parse<G, C, R>(arg: &mut R) -> Vec<Box<Renderable<G, C>>>
-    where C: graphics::character::CharacterCache<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture>  + 'static,
-          G: graphics::Graphics<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static,
+/* This should be called with buffered reader for performance reasons!
+ * spacing is put between the elements of nested lists, in unscaled pixels */
+pub fn custom_parse<G, C, R>(arg: &mut R, spacing: f64) -> Vec<Box<Renderable<G, C>>>
+    where C: graphics::character::CharacterCache<Texture = <G as graphics::Graphics>::Texture> + 'static,
+          G: graphics::Graphics + 'static,
+          <G as graphics::Graphics>::Texture: BarTexture,
           R: std::io::Read {
-    return CustomIter::new(arg).collect();
+    return CustomIter::new(arg, spacing).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use self::byteorder::{NativeEndian, WriteBytesExt};
+    use std::io::Cursor;
+
+    fn nums(out: &mut Vec<u8>, vals: &[u16]) {
+        for val in vals {
+            out.write_u16::<NativeEndian>(*val).unwrap();
+        }
+    }
+
+    fn floats(out: &mut Vec<u8>, vals: &[f32]) {
+        for val in vals {
+            out.write_f32::<NativeEndian>(*val).unwrap();
+        }
+    }
+
+    #[test]
+    fn points() {
+        let mut data = vec![2];
+        nums(&mut data, &[1, 2, 300, 4]);
+        let mut r = Cursor::new(data);
+        assert_eq!(parse_points(&mut r, false), vec![(1.0, 2.0), (300.0, 4.0)]);
+        assert_eq!(r.position(), 9);
+
+        let mut data = vec![1];
+        floats(&mut data, &[-1.5, 2.25]);
+        assert_eq!(parse_points(&mut Cursor::new(data), true), vec![(-1.5, 2.25)]);
+    }
+
+    #[test]
+    fn draw_commands() {
+        let mut data = vec![1];
+        nums(&mut data, &[0, 1, 10, 11]);
+        assert_eq!(parse_draw_elem(&mut Cursor::new(data), false),
+                   Some(DrawCommand::Rect(DrawRect::new(0.0, 1.0, 10.0, 11.0))));
+
+        /* The colour applies to the nested command */
+        let mut data = vec![3, 255, 0, 0, 255, 4];
+        nums(&mut data, &[5, 6, 3, 0]);
+        let circle = DrawCommand::Circle(DrawCircle::new(5.0, 6.0, 3.0, 0.0));
+        assert_eq!(parse_draw_elem(&mut Cursor::new(data), false),
+                   Some(DrawCommand::Col(DrawCol::new(circle, [1.0, 0.0, 0.0, 1.0]))));
+
+        let mut data = vec![8];
+        nums(&mut data, &[2]);
+        data.push(2);
+        nums(&mut data, &[0, 0, 10, 10]);
+        assert_eq!(parse_draw_elem(&mut Cursor::new(data), false),
+                   Some(DrawCommand::Polyline(DrawPolyline::new(vec![(0.0, 0.0), (10.0, 10.0)], 2.0))));
+    }
+
+    #[test]
+    fn draw_float_commands() {
+        /* 0xff keeps the element's coordinate type */
+        let mut data = vec![2, 0xff];
+        floats(&mut data, &[-1.0, 0.5, 8.0, 0.5, 1.0]);
+        assert_eq!(parse_draw_elem(&mut Cursor::new(data), true),
+                   Some(DrawCommand::Line(DrawLine::new(-1.0, 0.5, 8.0, 0.5, 1.0))));
+
+        let mut data = vec![1, 0];
+        floats(&mut data, &[0.0, 0.0, 4.0, 4.0]);
+        let rect = DrawCommand::Rect(DrawRect::new(0.0, 0.0, 4.0, 4.0));
+        assert_eq!(parse_draw_elem(&mut Cursor::new(data), true),
+                   Some(DrawCommand::Coords(DrawCoords::new(rect, Coordtype::Absolute))));
+    }
+
+    #[test]
+    fn draw_width() {
+        let width = |val: f32| {
+            let mut data = Vec::new();
+            floats(&mut data, &[val]);
+            parse_draw_width(&mut Cursor::new(data))
+        };
+
+        assert_eq!(width(12.5), DrawWidth::Fixed(12.5));
+        assert_eq!(width(0.0), DrawWidth::Fixed(0.0));
+        assert_eq!(width(-1.0), DrawWidth::Extents);
+        assert_eq!(width(std::f32::NAN), DrawWidth::Extents);
+        assert_eq!(width(std::f32::INFINITY), DrawWidth::Extents);
+    }
+
+    #[test]
+    fn paths() {
+        let mut data = vec![9, 0xff];
+        floats(&mut data, &[1.0]);
+        data.push(4);
+        data.push(0);
+        floats(&mut data, &[0.0, 0.0]);
+        data.push(1);
+        floats(&mut data, &[10.0, 0.0]);
+        data.push(2);
+        floats(&mut data, &[10.0, 5.0, 5.0, 10.0, 0.0, 10.0]);
+        data.push(3);
+        let segments = vec![PathSegment::Move(0.0, 0.0), PathSegment::Line(10.0, 0.0),
+                            PathSegment::Curve(10.0, 5.0, 5.0, 10.0, 0.0, 10.0), PathSegment::Close];
+        assert_eq!(parse_draw_elem(&mut Cursor::new(data), true),
+                   Some(DrawCommand::Path(DrawPath::new(segments, 1.0))));
+
+        /* The arguments of an unknown segment can't be skipped */
+        let mut data = vec![9];
+        nums(&mut data, &[0]);
+        data.extend_from_slice(&[2, 3, 7]);
+        assert_eq!(parse_draw_elem(&mut Cursor::new(data), false), None);
+    }
+
+    #[test]
+    fn broken_commands_drop_the_element() {
+        assert_eq!(parse_draw_elem(&mut Cursor::new(vec![42]), false), None);
+
+        /* Absolute, width -1, a rect and then a path with an unknown segment */
+        let mut data = vec![0];
+        floats(&mut data, &[-1.0]);
+        data.extend_from_slice(&[2, 1, 0xff]);
+        floats(&mut data, &[0.0, 0.0, 4.0, 4.0]);
+        data.extend_from_slice(&[9, 0xff]);
+        floats(&mut data, &[0.0]);
+        data.extend_from_slice(&[1, 9]);
+        assert!(parse_draw_float(&mut Cursor::new(data)).is_none());
+    }
+
+    /* Header of an InlineImage without the type, drawn at the image's size */
+    fn inline_header(encoding: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        nums(&mut data, &[0, 0]);
+        data.write_u64::<NativeEndian>(42).unwrap();
+        data.push(encoding);
+        return data;
+    }
+
+    #[test]
+    fn inline_images() {
+        let mut data = inline_header(0);
+        nums(&mut data, &[2, 1]);
+        data.write_u32::<NativeEndian>(12).unwrap();
+        data.extend_from_slice(&[7; 12]);
+        let image = parse_inline_image(&mut Cursor::new(data)).unwrap().unwrap();
+        assert_eq!(image.hash, 42);
+        match image.data {
+            InlineData::Rgba { width, height, stride, data } => {
+                assert_eq!((width, height, stride), (2, 1, 12));
+                assert_eq!(data, vec![7; 12]);
+            },
+            InlineData::Png(_) => panic!("Got a PNG instead of RGBA"),
+        }
+
+        /* Rows shorter than the width */
+        let mut data = inline_header(0);
+        nums(&mut data, &[4, 1]);
+        data.write_u32::<NativeEndian>(8).unwrap();
+        data.extend_from_slice(&[0; 8]);
+        assert!(parse_inline_image(&mut Cursor::new(data)).unwrap().is_none());
+
+        /* More than the limit isn't allocated, whatever the stream claims */
+        let mut data = inline_header(0);
+        nums(&mut data, &[0xffff, 0xffff]);
+        data.write_u32::<NativeEndian>(0xffffffff).unwrap();
+        assert!(parse_inline_image(&mut Cursor::new(data)).is_err());
+
+        let mut data = inline_header(1);
+        data.write_u32::<NativeEndian>(0xffffffff).unwrap();
+        assert!(parse_inline_image(&mut Cursor::new(data)).is_err());
+
+        /* Less data than announced */
+        let mut data = inline_header(1);
+        data.write_u32::<NativeEndian>(1000).unwrap();
+        data.extend_from_slice(&[0; 10]);
+        assert!(parse_inline_image(&mut Cursor::new(data)).is_err());
+
+        /* The length decides how much to read, unknown encodings can't be skipped */
+        assert!(parse_inline_image(&mut Cursor::new(inline_header(2))).is_err());
+    }
+
+    /* A text element without the type */
+    fn text(data: &mut Vec<u8>, text: &str) {
+        nums(data, &[text.len() as u16]);
+        data.extend_from_slice(text.as_bytes());
+        data.push(0);
+    }
+
+    #[test]
+    fn rejected_images_are_skipped() {
+        /* Too large */
+        let mut data = inline_header(1);
+        data.write_u32::<NativeEndian>(MAX_INLINE_BYTES as u32 + 1).unwrap();
+        data.extend_from_slice(&vec![0; MAX_INLINE_BYTES + 1]);
+        text(&mut data, "after");
+        let mut r = Cursor::new(data);
+        assert!(parse_inline_image(&mut r).unwrap().is_none());
+        assert_eq!(parse_text(&mut r).0, "after");
+
+        /* Rows shorter than the width */
+        let mut data = inline_header(0);
+        nums(&mut data, &[4, 2]);
+        data.write_u32::<NativeEndian>(8).unwrap();
+        data.extend_from_slice(&[0; 16]);
+        text(&mut data, "after");
+        let mut r = Cursor::new(data);
+        assert!(parse_inline_image(&mut r).unwrap().is_none());
+        assert_eq!(parse_text(&mut r).0, "after");
+    }
 }
@@ -1,13 +1,13 @@
 extern crate graphics;
-extern crate opengl_graphics;
 extern crate num_traits;
 
-use modules::renderable::Renderable;
+use modules::renderable::{BarTexture, Renderable};
 use modules::ongystr::OngyStr;
 use modules::colored::Colored;
 use modules::ongypos::OngyPos;
 use modules::ongyrect::OngyRect;
 use modules::ongyimage::OngyImage;
+use modules::ongylist::OngyList;
 
 use std;
 use std::vec::Vec;
@@ -17,13 +17,16 @@ use self::num_traits::Num;
 
 struct DzenIter<G, C> {
     text: String,
+    /* For the lists colours apply to */
+    spacing: f64,
     g: std::marker::PhantomData<G>,
     c: std::marker::PhantomData<C>,
 }
 
 impl<G, C> DzenIter<G, C> {
-    fn new(text: &str) -> Self {
+    fn new(text: &str, spacing: f64) -> Self {
         return DzenIter{ text: String::from(text),
+                         spacing: spacing,
                          g: std::marker::PhantomData,
                          c: std::marker::PhantomData  };
     }
@@ -40,20 +43,52 @@ fn parse_pos(text: &str) -> Option<OngyPos> {
      * dzen has all of them starting with a '_'
      */
     if x.starts_with('_') {
-        /* This exactly removes the offset from having an element in the trait
-         * Renderable implementation
-         */
-        return Some(OngyPos(-4f64));
+        return None;
     }
 
     return match f64::from_str_radix(x, 10) {
         Ok(val) => Some(OngyPos(val)),
-        Err(_) => None
+        Err(_) => {
+            println!("Couldn't parse dzen position: {}", x);
+            None
+        }
     }
 }
 
+/* Either side of an image size may be left out, e.g. "x16" or "24x".
+ * Sides that are given have to be positive */
+fn parse_image_size(text: &str) -> Option<(Option<f64>, Option<f64>)> {
+    let i = match text.find('x') {
+        Some(i) => i,
+        None => return None,
+    };
+
+    let side = |x: &str| {
+        if x.is_empty() {
+            return Some(None);
+        }
+        return match f64::from_str_radix(x, 10) {
+            Ok(val) if val > 0.0 && val.is_finite() => Some(Some(val)),
+            _ => None,
+        };
+    };
+    return match (side(&text[..i]), side(&text[i + 1..])) {
+        (Some(w), Some(h)) => Some((w, h)),
+        _ => None,
+    };
+}
+
+/* The path may be followed by ";WxH" to set the size */
 fn parse_image(text: &str) -> Option<OngyImage> {
-    return Some(OngyImage(String::from(text).replace(".xbm", ".bmp")));
+    let (path, size) = match text.rfind(';') {
+        Some(i) => match parse_image_size(&text[i + 1..]) {
+            Some(x) => (&text[..i], x),
+            None => (text, (None, None)),
+        },
+        None => (text, (None, None)),
+    };
+
+    return Some(OngyImage { path: String::from(path), width: size.0, height: size.1 });
 }
 
 fn parse_rect(text: &str) -> Option<OngyRect> {
@@ -115,15 +150,66 @@ fn parse_color(hex_str: &str) -> Option<[f32; 4]> {
     let green = &hex_str[3..5];
     let blue  = &hex_str[5..7];
     // TODO: Remove the unwrap()...
-    return Some([f32::from_str_radix(red, 16).unwrap(),
-                 f32::from_str_radix(green, 16).unwrap(),
-                 f32::from_str_radix(blue, 16).unwrap(),
+    return Some([f32::from_str_radix(red, 16).unwrap() / 255.0,
+                 f32::from_str_radix(green, 16).unwrap() / 255.0,
+                 f32::from_str_radix(blue, 16).unwrap() / 255.0,
                  1.0]);
 }
 
+impl<G, C> DzenIter<G, C>
+    where C: graphics::character::CharacterCache<Texture = <G as graphics::Graphics>::Texture> + 'static,
+          G: graphics::Graphics + 'static,
+          <G as graphics::Graphics>::Texture: BarTexture, {
+
+    /* Handles ^fg() and ^bg(). Everything up to the next command of the same
+     * kind is parsed recursively and gets the colour applied */
+    fn parse_colored(&mut self, cmd: &str, fg: bool) -> Option<Box<Renderable<G, C>>> {
+        let reset = format!("{})", cmd);
+        if self.text.starts_with(reset.as_str()) {
+            /* Resetting doesn't draw anything, go on with the rest */
+            self.text = String::from(&self.text.as_str()[reset.len() .. ]);
+            return self.next();
+        }
+
+        let i = match self.text.find(')') {
+            Some(i) => i,
+            None => {
+                std::io::stderr().write(format!("Found '{}', but couldn't find closing parens!", cmd).as_bytes()).unwrap();
+                return None;
+            }
+        };
+
+        let c = match parse_color(&self.text.as_str()[cmd.len() .. i]) {
+            Some(c) => c,
+            None => return None,
+        };
+
+        let tmp = match self.text.as_str()[i + 1..].find(cmd) {
+            Some (j) => {
+                let tmp = dzen_parse(&self.text.as_str()[i + 1 .. i + 1 + j], self.spacing);
+                self.text = String::from(&self.text.as_str()[i + 1 + j ..]);
+                tmp
+            }
+            None => {
+                let tmp = dzen_parse(&self.text.as_str()[i + 1 ..], self.spacing);
+                self.text.clear();
+                tmp
+            }
+        };
+
+        let list = Box::new(OngyList { elems: tmp, spacing: self.spacing });
+        if fg {
+            return Some(Box::new(Colored{ color: Some(c), background: None, elem: list}));
+        } else {
+            return Some(Box::new(Colored{ color: None, background: Some(c), elem: list}));
+        }
+    }
+}
+
 impl<G, C> Iterator for DzenIter<G, C>
-    where C: graphics::character::CharacterCache<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static,
-          G: graphics::Graphics<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static, {
+    where C: graphics::character::CharacterCache<Texture = <G as graphics::Graphics>::Texture> + 'static,
+          G: graphics::Graphics + 'static,
+          <G as graphics::Graphics>::Texture: BarTexture, {
     type Item=Box<Renderable<G, C>>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -138,47 +224,22 @@ impl<G, C> Iterator for DzenIter<G, C>
             }
             None => {
                 if self.text.starts_with("^fg(") {
-                    if self.text.starts_with("^fg()") {
-                        self.text = String::from(&self.text.as_str()[5 .. ]);
-                        return Some(Box::new(OngyPos(-4.0)));
-                    }
-                    return match self.text.find(')') {
-                        Some(i) => {
-                            match parse_color(&self.text.as_str()[4 .. i]) {
-                                Some(c) =>  {
-                                     match self.text.as_str()[i + 1..].find("^fg(") {
-                                        Some (j) => {
-                                            let tmp = dzen_parse(&self.text.as_str()[i + 1 .. i + 1 + j]);
-                                            self.text = String::from(&self.text.as_str()[i + 1 + j ..]);
-                                            return Some(Box::new(Colored{ color: c, elem: Box::new(tmp)}));
-                                        }
-                                        None => {
-                                            let tmp = dzen_parse(&self.text.as_str()[i + 1 ..]);
-                                            self.text.clear();
-                                            return Some(Box::new(Colored{ color: c, elem: Box::new(tmp)}));
-                                        }
-                                    }
-                                }
-                                None => None
-                            }
-                        }
-                        None => {
-                            std::io::stderr().write(b"Found '^fg(', but couldn't find closing parens!").unwrap();
-                            return None;
-                        }
-                    }
+                    return self.parse_colored("^fg(", true);
+                }
+                if self.text.starts_with("^bg(") {
+                    return self.parse_colored("^bg(", false);
                 }
                 if self.text.starts_with("^p(") {
                     return match self.text.find(')') {
                         Some(i) => {
-                            match parse_pos(&self.text.as_str()[3 .. i]) {
-                                Some(r) =>  {
-                                    self.text = String::from(&self.text.as_str()[i + 1 ..]);
-                                    return Some(Box::new(r));
-                                }
-                                None => None
-                                }
+                            let pos = parse_pos(&self.text.as_str()[3 .. i]);
+                            self.text = String::from(&self.text.as_str()[i + 1 ..]);
+                            match pos {
+                                Some(r) => Some(Box::new(r) as Box<Renderable<G, C>>),
+                                /* Nothing to draw, go on with the rest */
+                                None => self.next(),
                             }
+                        }
                         None => {
                             std::io::stderr().write(b"Found '^fg(', but couldn't find closing parens!").unwrap();
                             return None;
@@ -219,19 +280,6 @@ impl<G, C> Iterator for DzenIter<G, C>
                         }
                     }
                 }
-                if self.text.starts_with("^bg(") {
-                    //std::io::stderr().write(b"Sorry, ongybar currently ignores background colours\n").unwrap();
-                    match self.text.find(')') {
-                        None => {
-                            std::io::stderr().write(b"Found ^bg(, but not closing parens").unwrap();
-                            return None;
-                        }
-                        Some(i) => {
-                            self.text = String::from(&self.text.as_str()[i + 1..]);
-                            return Some(Box::new(OngyPos(-4.0)));
-                        }
-                    }
-                }
                 if self.text.starts_with("^pa(") {
                     //std::io::stderr().write(b"Sorry, ongybar currently ignores total positions\n").unwrap();
                     match self.text.find(')') {
@@ -241,7 +289,7 @@ impl<G, C> Iterator for DzenIter<G, C>
                         }
                         Some(i) => {
                             self.text = String::from(&self.text.as_str()[i + 1..]);
-                            return Some(Box::new(OngyPos(-4.0)));
+                            return self.next();
                         }
                     }
                 }
@@ -252,8 +300,39 @@ impl<G, C> Iterator for DzenIter<G, C>
     }
 }
 
-pub fn dzen_parse<G, C>(arg: &str) -> Vec<Box<Renderable<G, C>>>
-    where C: graphics::character::CharacterCache<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture>  + 'static,
-          G: graphics::Graphics<Texture = <opengl_graphics::GlGraphics as graphics::Graphics>::Texture> + 'static, {
-    return DzenIter::new(arg).collect();
+/// spacing is put between the elements of nested lists, in unscaled pixels
+pub fn dzen_parse<G, C>(arg: &str, spacing: f64) -> Vec<Box<Renderable<G, C>>>
+    where C: graphics::character::CharacterCache<Texture = <G as graphics::Graphics>::Texture> + 'static,
+          G: graphics::Graphics + 'static,
+          <G as graphics::Graphics>::Texture: BarTexture, {
+    return DzenIter::new(arg, spacing).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_size() {
+        assert_eq!(parse_image_size("24x16"), Some((Some(24.0), Some(16.0))));
+        assert_eq!(parse_image_size("x16"), Some((None, Some(16.0))));
+        assert_eq!(parse_image_size("24x"), Some((Some(24.0), None)));
+        assert_eq!(parse_image_size("x"), Some((None, None)));
+        assert_eq!(parse_image_size("16"), None);
+        assert_eq!(parse_image_size("axb"), None);
+        assert_eq!(parse_image_size("-5x16"), None);
+        assert_eq!(parse_image_size("24x0"), None);
+    }
+
+    #[test]
+    fn image() {
+        let image = parse_image("/usr/share/icons/bat.png;x12").unwrap();
+        assert_eq!(image.path, "/usr/share/icons/bat.png");
+        assert_eq!((image.width, image.height), (None, Some(12.0)));
+
+        /* Not a size, so part of the path */
+        let image = parse_image("a;b.png").unwrap();
+        assert_eq!(image.path, "a;b.png");
+        assert_eq!((image.width, image.height), (None, None));
+    }
 }
@@ -0,0 +1,244 @@
+// Software renderer
+//
+// Rasterizes the triangles graphics produces on the CPU. This is used when
+// there's no usable OpenGL, e.g. in VNC sessions, on Xvfb or thin clients.
+// The frame ends up in a BGRA buffer that can be put on the window as is.
+
+extern crate graphics;
+extern crate image;
+
+use modules::renderable::BarTexture;
+use surface::Surface;
+
+use std::path::Path;
+
+pub struct Texture {
+    width: u32,
+    height: u32,
+    /* RGBA, not premultiplied */
+    data: Vec<u8>,
+}
+
+impl graphics::ImageSize for Texture {
+    fn get_size(&self) -> (u32, u32) {
+        return (self.width, self.height);
+    }
+}
+
+impl BarTexture for Texture {
+    fn from_path(path: &Path) -> Result<Self, String> {
+        let image = match image::open(path) {
+            Ok(x) => x.to_rgba(),
+            Err(x) => return Err(format!("{}", x)),
+        };
+
+        let (width, height) = image.dimensions();
+        return Ok(Texture { width: width, height: height, data: image.into_raw() });
+    }
+
+    fn from_rgba(data: &[u8], width: u32, height: u32) -> Result<Self, String> {
+        if data.len() != (width * height * 4) as usize {
+            return Err(format!("Expected {} bytes for {}x{}, got {}",
+                               width * height * 4, width, height, data.len()));
+        }
+
+        return Ok(Texture { width: width, height: height, data: data.to_vec() });
+    }
+}
+
+impl Texture {
+    /* Nearest neighbour, uv outside of [0, 1] is clamped */
+    fn sample(&self, u: f32, v: f32) -> [f32; 4] {
+        let x = ((u * self.width as f32) as i64).max(0).min(self.width as i64 - 1) as usize;
+        let y = ((v * self.height as f32) as i64).max(0).min(self.height as i64 - 1) as usize;
+        let pos = (y * self.width as usize + x) * 4;
+
+        return [self.data[pos] as f32 / 255.0,
+                self.data[pos + 1] as f32 / 255.0,
+                self.data[pos + 2] as f32 / 255.0,
+                self.data[pos + 3] as f32 / 255.0];
+    }
+}
+
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    /* BGRA, premultiplied, which is what X wants for ARGB visuals */
+    data: Vec<u8>,
+    /* x, y, width, height of the current viewport, top left origin */
+    viewport: [i32; 4],
+}
+
+/* Twice the signed area of the triangle a, b, p */
+fn edge(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f32 {
+    return (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0]);
+}
+
+/* Pixels exactly on an edge belong to only one of the triangles that share
+ * it. Those walk the edge in opposite directions, so decide by direction */
+fn covers(w: f32, a: [f32; 2], b: [f32; 2]) -> bool {
+    if w != 0.0 {
+        return w > 0.0;
+    }
+
+    let dy = b[1] - a[1];
+    return dy < 0.0 || (dy == 0.0 && b[0] > a[0]);
+}
+
+impl Canvas {
+    pub fn new() -> Self {
+        return Canvas { width: 0, height: 0, data: Vec::new(), viewport: [0, 0, 0, 0] };
+    }
+
+    /// The frame as RGBA without premultiplied alpha, e.g. to save it as image
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(self.data.len());
+        for pixel in self.data.chunks(4) {
+            let a = pixel[3] as u32;
+            let unmul = |c: u8| if a == 0 { 0 } else { (c as u32 * 255 / a).min(255) as u8 };
+            ret.push(unmul(pixel[2]));
+            ret.push(unmul(pixel[1]));
+            ret.push(unmul(pixel[0]));
+            ret.push(pixel[3]);
+        }
+
+        return ret;
+    }
+
+    /* graphics hands out normalized device coordinates for the viewport */
+    fn to_pixels(&self, v: [f32; 2]) -> [f32; 2] {
+        return [self.viewport[0] as f32 + (v[0] + 1.0) / 2.0 * self.viewport[2] as f32,
+                self.viewport[1] as f32 + (1.0 - v[1]) / 2.0 * self.viewport[3] as f32];
+    }
+
+    fn blend(&mut self, x: usize, y: usize, c: [f32; 4]) {
+        let pos = (y * self.width as usize + x) * 4;
+        let a = c[3];
+        let keep = 1.0 - a;
+
+        self.data[pos] = (c[2] * a * 255.0 + self.data[pos] as f32 * keep) as u8;
+        self.data[pos + 1] = (c[1] * a * 255.0 + self.data[pos + 1] as f32 * keep) as u8;
+        self.data[pos + 2] = (c[0] * a * 255.0 + self.data[pos + 2] as f32 * keep) as u8;
+        self.data[pos + 3] = (a * 255.0 + self.data[pos + 3] as f32 * keep) as u8;
+    }
+
+    /* Call f with the pixel and barycentric coordinates of everything the
+     * triangle covers inside of the viewport */
+    fn raster<F>(&mut self, tri: &[[f32; 2]], mut f: F)
+        where F: FnMut(&mut Self, usize, usize, [f32; 3]) {
+        let mut v = [self.to_pixels(tri[0]), self.to_pixels(tri[1]), self.to_pixels(tri[2])];
+        let mut order = [0, 1, 2];
+        let mut area = edge(v[0], v[1], v[2]);
+        if area == 0.0 {
+            return;
+        }
+        /* Always walk the triangle the same way round */
+        if area < 0.0 {
+            v.swap(1, 2);
+            order.swap(1, 2);
+            area = -area;
+        }
+
+        let left = self.viewport[0].max(0);
+        let top = self.viewport[1].max(0);
+        let right = (self.viewport[0] + self.viewport[2]).min(self.width as i32);
+        let bottom = (self.viewport[1] + self.viewport[3]).min(self.height as i32);
+
+        let min_x = (v[0][0].min(v[1][0]).min(v[2][0]).floor() as i32).max(left);
+        let min_y = (v[0][1].min(v[1][1]).min(v[2][1]).floor() as i32).max(top);
+        let max_x = (v[0][0].max(v[1][0]).max(v[2][0]).ceil() as i32).min(right);
+        let max_y = (v[0][1].max(v[1][1]).max(v[2][1]).ceil() as i32).min(bottom);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = [x as f32 + 0.5, y as f32 + 0.5];
+                let w0 = edge(v[1], v[2], p);
+                let w1 = edge(v[2], v[0], p);
+                let w2 = edge(v[0], v[1], p);
+
+                if !covers(w0, v[1], v[2]) || !covers(w1, v[2], v[0]) || !covers(w2, v[0], v[1]) {
+                    continue;
+                }
+
+                /* Hand out the weights in the order the caller gave the vertices */
+                let mut weights = [0.0; 3];
+                weights[order[0]] = w0 / area;
+                weights[order[1]] = w1 / area;
+                weights[order[2]] = w2 / area;
+                f(self, x as usize, y as usize, weights);
+            }
+        }
+    }
+}
+
+impl graphics::Graphics for Canvas {
+    type Texture = Texture;
+
+    fn clear_color(&mut self, color: graphics::types::Color) {
+        let pixel = [(color[2] * 255.0) as u8, (color[1] * 255.0) as u8,
+                     (color[0] * 255.0) as u8, (color[3] * 255.0) as u8];
+        for chunk in self.data.chunks_mut(4) {
+            chunk.copy_from_slice(&pixel);
+        }
+    }
+
+    fn clear_stencil(&mut self, _: u8) {
+    }
+
+    fn tri_list<F>(&mut self, _: &graphics::DrawState, color: &[f32; 4], mut f: F)
+        where F: FnMut(&mut FnMut(&[[f32; 2]])) {
+        let color = *color;
+        f(&mut |vertices: &[[f32; 2]]| {
+            for tri in vertices.chunks(3) {
+                if tri.len() == 3 {
+                    self.raster(tri, |canvas, x, y, _| canvas.blend(x, y, color));
+                }
+            }
+        });
+    }
+
+    fn tri_list_uv<F>(&mut self, _: &graphics::DrawState, color: &[f32; 4],
+                      texture: &Texture, mut f: F)
+        where F: FnMut(&mut FnMut(&[[f32; 2]], &[[f32; 2]])) {
+        let color = *color;
+        f(&mut |vertices: &[[f32; 2]], uvs: &[[f32; 2]]| {
+            for (tri, uv) in vertices.chunks(3).zip(uvs.chunks(3)) {
+                if tri.len() < 3 || uv.len() < 3 {
+                    continue;
+                }
+
+                self.raster(tri, |canvas, x, y, w| {
+                    let u = uv[0][0] * w[0] + uv[1][0] * w[1] + uv[2][0] * w[2];
+                    let v = uv[0][1] * w[0] + uv[1][1] * w[1] + uv[2][1] * w[2];
+                    let texel = texture.sample(u, v);
+                    canvas.blend(x, y, [texel[0] * color[0], texel[1] * color[1],
+                                        texel[2] * color[2], texel[3] * color[3]]);
+                });
+            }
+        });
+    }
+}
+
+impl Surface for Canvas {
+    fn draw<F, U>(&mut self, viewport: graphics::Viewport, f: F) -> U
+        where F: FnOnce(graphics::Context, &mut Self) -> U {
+        /* Viewports have their origin in the bottom left, like GL */
+        let rect = viewport.rect;
+        self.viewport = [rect[0], self.height as i32 - rect[1] - rect[3], rect[2], rect[3]];
+
+        return f(graphics::Context::new_viewport(viewport), self);
+    }
+
+    fn begin(&mut self, width: u32, height: u32) {
+        if self.width != width || self.height != height {
+            self.width = width;
+            self.height = height;
+            self.data = vec![0; (width * height * 4) as usize];
+        }
+        self.viewport = [0, 0, width as i32, height as i32];
+    }
+
+    fn pixels(&self) -> Option<&[u8]> {
+        return Some(&self.data);
+    }
+}
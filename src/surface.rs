@@ -0,0 +1,37 @@
+// Render targets
+//
+// Everything the bar draws goes through graphics::Graphics. On top of that
+// the window needs to limit drawing to parts of the bar, and hand the result
+// to the X server once a frame is done. This is what differs between the
+// OpenGL renderer and the software one.
+
+extern crate graphics;
+extern crate opengl_graphics;
+
+pub trait Surface: graphics::Graphics {
+    /// Draw into the part of the frame described by viewport.
+    /// Anything outside of it is clipped
+    fn draw<F, U>(&mut self, viewport: graphics::Viewport, f: F) -> U
+        where F: FnOnce(graphics::Context, &mut Self) -> U;
+
+    /// Start a new frame with the given size
+    fn begin(&mut self, width: u32, height: u32);
+
+    /// The pixels of the last frame as BGRA rows.
+    /// None if they never left the GPU
+    fn pixels(&self) -> Option<&[u8]>;
+}
+
+impl Surface for opengl_graphics::GlGraphics {
+    fn draw<F, U>(&mut self, viewport: graphics::Viewport, f: F) -> U
+        where F: FnOnce(graphics::Context, &mut Self) -> U {
+        return opengl_graphics::GlGraphics::draw(self, viewport, f);
+    }
+
+    fn begin(&mut self, _: u32, _: u32) {
+    }
+
+    fn pixels(&self) -> Option<&[u8]> {
+        return None;
+    }
+}
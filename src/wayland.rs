@@ -0,0 +1,1236 @@
+// Wayland backend
+//
+// Talks the wayland wire protocol directly, there's only a handful of
+// requests and events we need. Bars are wlr-layer-shell surfaces, which is
+// what sway and other wlroots compositors offer for docks and panels.
+// Frames come from the software renderer and are handed over through wl_shm.
+// Outputs are named by wl_output itself since version 4, and through
+// xdg-output before that, so the names match what RandR calls them.
+
+extern crate libc;
+
+use ::config;
+use backend::{Backend, Frame};
+use software::Canvas;
+use surface::Surface;
+
+use std;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Read;
+use std::os::raw::{c_int, c_void};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+/* The display always has this id */
+const DISPLAY_ID: u32 = 1;
+
+/* libwayland doesn't send or accept anything larger */
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+/* From the wl_shm protocol */
+const SHM_FORMAT_ARGB8888: u32 = 0;
+
+/* From the wlr-layer-shell protocol */
+const LAYER_TOP: u32 = 2;
+const ANCHOR_TOP: u32 = 1;
+const ANCHOR_BOTTOM: u32 = 2;
+const ANCHOR_LEFT: u32 = 4;
+const ANCHOR_RIGHT: u32 = 8;
+
+enum Arg<'a> {
+    Int(i32),
+    Uint(u32),
+    Str(&'a str),
+    Fd(RawFd),
+}
+
+fn push_u32(out: &mut Vec<u8>, val: u32) {
+    out.extend_from_slice(&[val as u8, (val >> 8) as u8, (val >> 16) as u8, (val >> 24) as u8]);
+}
+
+/* Append a message to out. File descriptors aren't part of the data, they
+ * go into fds to be sent alongside it */
+fn marshal(out: &mut Vec<u8>, fds: &mut Vec<RawFd>, obj: u32, opcode: u16, args: &[Arg]) {
+    let start = out.len();
+    push_u32(out, obj);
+    push_u32(out, 0);
+
+    for arg in args {
+        match arg {
+            &Arg::Int(x) => push_u32(out, x as u32),
+            &Arg::Uint(x) => push_u32(out, x),
+            &Arg::Str(x) => {
+                /* Strings include their terminating 0 and are padded to 32 bits */
+                push_u32(out, x.len() as u32 + 1);
+                out.extend_from_slice(x.as_bytes());
+                out.push(0);
+                while out.len() % 4 != 0 {
+                    out.push(0);
+                }
+            },
+            &Arg::Fd(x) => fds.push(x),
+        }
+    }
+
+    let size = (out.len() - start) as u32;
+    let word = (size << 16) | opcode as u32;
+    out[start + 4] = word as u8;
+    out[start + 5] = (word >> 8) as u8;
+    out[start + 6] = (word >> 16) as u8;
+    out[start + 7] = (word >> 24) as u8;
+}
+
+/// The socket and the (de)serialization of messages on it
+struct Connection {
+    socket: UnixStream,
+    out: Vec<u8>,
+    out_fds: Vec<RawFd>,
+    input: Vec<u8>,
+    next_id: u32,
+    /* Set once the connection is lost. Nothing is sent or read after that */
+    error: Option<String>,
+}
+
+impl Connection {
+    fn new(socket: UnixStream) -> Self {
+        return Connection { socket: socket, out: Vec::new(), out_fds: Vec::new(),
+                            input: Vec::new(), next_id: 2, error: None };
+    }
+
+    fn connect() -> Result<Self, String> {
+        let display = match std::env::var("WAYLAND_DISPLAY") {
+            Ok(x) => x,
+            Err(_) => String::from("wayland-0"),
+        };
+
+        /* WAYLAND_DISPLAY may be a full path already */
+        let mut path = PathBuf::from(&display);
+        if !path.is_absolute() {
+            match std::env::var("XDG_RUNTIME_DIR") {
+                Ok(x) => path = PathBuf::from(x).join(display),
+                Err(_) => return Err(String::from("XDG_RUNTIME_DIR isn't set")),
+            }
+        }
+
+        return match UnixStream::connect(&path) {
+            Ok(x) => Ok(Connection::new(x)),
+            Err(x) => Err(format!("Couldn't connect to {}: {}", path.display(), x)),
+        };
+    }
+
+    /* Only the first error is interesting, the rest follow from it */
+    fn fail(&mut self, error: String) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+        self.out.clear();
+        self.out_fds.clear();
+    }
+
+    fn new_id(&mut self) -> u32 {
+        let ret = self.next_id;
+        self.next_id += 1;
+        return ret;
+    }
+
+    /* Queue a request, flush sends it */
+    fn send(&mut self, obj: u32, opcode: u16, args: &[Arg]) {
+        if self.error.is_some() {
+            return;
+        }
+        marshal(&mut self.out, &mut self.out_fds, obj, opcode, args);
+    }
+
+    fn flush(&mut self) {
+        let mut sent = 0;
+        while sent < self.out.len() && self.error.is_none() {
+            let ret = unsafe {
+                let mut iov = libc::iovec {
+                    iov_base: self.out[sent..].as_ptr() as *mut c_void,
+                    iov_len: self.out.len() - sent,
+                };
+                let mut msg: libc::msghdr = std::mem::zeroed();
+                msg.msg_iov = &mut iov;
+                msg.msg_iovlen = 1;
+
+                /* File descriptors go along with the first chunk of data */
+                let fd_len = (self.out_fds.len() * std::mem::size_of::<c_int>()) as u32;
+                let mut control = vec![0u64; libc::CMSG_SPACE(fd_len) as usize / 8 + 1];
+                if !self.out_fds.is_empty() {
+                    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+                    msg.msg_controllen = libc::CMSG_SPACE(fd_len) as _;
+                    let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                    (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                    (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+                    (*cmsg).cmsg_len = libc::CMSG_LEN(fd_len) as _;
+                    std::ptr::copy_nonoverlapping(self.out_fds.as_ptr(),
+                                                  libc::CMSG_DATA(cmsg) as *mut c_int,
+                                                  self.out_fds.len());
+                }
+
+                libc::sendmsg(self.socket.as_raw_fd(), &msg, 0)
+            };
+
+            if ret < 0 {
+                let error = std::io::Error::last_os_error();
+                /* The compositor is busy reading, wait until there's room */
+                if error.kind() == std::io::ErrorKind::WouldBlock {
+                    let mut pfd = libc::pollfd { fd: self.socket.as_raw_fd(), events: libc::POLLOUT, revents: 0 };
+                    unsafe { libc::poll(&mut pfd, 1, -1) };
+                    continue;
+                }
+                if error.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                self.fail(format!("Lost the connection to the compositor: {}", error));
+                return;
+            }
+            sent += ret as usize;
+            self.out_fds.clear();
+        }
+
+        self.out.clear();
+    }
+
+    /* Read whatever is there. Blocks if there's nothing, unless the socket
+     * is non-blocking. Returns false if nothing was read */
+    fn read(&mut self) -> bool {
+        if self.error.is_some() {
+            return false;
+        }
+
+        let mut buffer = [0u8; 4096];
+        match self.socket.read(&mut buffer) {
+            Ok(0) => {
+                self.fail(String::from("The compositor closed the connection"));
+                return false;
+            },
+            Ok(x) => {
+                self.input.extend_from_slice(&buffer[..x]);
+                return true;
+            },
+            Err(ref x) if x.kind() == std::io::ErrorKind::WouldBlock => return false,
+            Err(ref x) if x.kind() == std::io::ErrorKind::Interrupted => return true,
+            Err(x) => {
+                self.fail(format!("Couldn't read from the compositor: {}", x));
+                return false;
+            },
+        }
+    }
+
+    /* Get the next complete event as (object, opcode, arguments) */
+    fn next_event(&mut self) -> Option<(u32, u16, Vec<u8>)> {
+        if self.input.len() < 8 {
+            return None;
+        }
+
+        let obj = read_u32(&self.input[0..4]);
+        let word = read_u32(&self.input[4..8]);
+        let size = (word >> 16) as usize;
+        /* There's no way to find the next message after a broken header */
+        if size < 8 || size > MAX_MESSAGE_SIZE {
+            self.fail(format!("Got a message of {} bytes from the compositor", size));
+            self.input.clear();
+            return None;
+        }
+        if self.input.len() < size {
+            return None;
+        }
+
+        let args = self.input[8..size].to_vec();
+        self.input.drain(..size);
+        return Some((obj, (word & 0xffff) as u16, args));
+    }
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    return data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16 | (data[3] as u32) << 24;
+}
+
+/// Walks the arguments of an event
+struct Args<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Args<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        return Args { data: data, pos: 0 };
+    }
+
+    fn uint(&mut self) -> u32 {
+        if self.pos + 4 > self.data.len() {
+            return 0;
+        }
+        let ret = read_u32(&self.data[self.pos..]);
+        self.pos += 4;
+        return ret;
+    }
+
+    fn int(&mut self) -> i32 {
+        return self.uint() as i32;
+    }
+
+    fn string(&mut self) -> String {
+        let len = self.uint() as usize;
+        /* A length running past the end only gets what's there */
+        let end = std::cmp::min(self.pos.saturating_add(len), self.data.len());
+        /* Without the terminating 0 */
+        let ret = String::from_utf8_lossy(&self.data[self.pos..end]).trim_right_matches('\0').to_string();
+        self.pos = std::cmp::min(self.pos.saturating_add(len.saturating_add(3) & !3), self.data.len());
+        return ret;
+    }
+}
+
+/// What the ids we handed out are
+enum Object {
+    Registry,
+    Callback,
+    Compositor,
+    Shm,
+    LayerShell,
+    XdgOutputManager,
+    /* The global name of the output */
+    Output(u32),
+    /* The global name of the output this describes */
+    XdgOutput(u32),
+    Surface,
+    LayerSurface,
+    Pool,
+    Buffer,
+}
+
+struct Output {
+    /* The global name in the registry */
+    global: u32,
+    id: u32,
+    version: u32,
+    /* Only used for the name on outputs older than version 4, 0 without one */
+    xdg: u32,
+    name: String,
+    x: i32,
+    y: i32,
+    /* Of the current mode, in pixels */
+    width: i32,
+    height: i32,
+    mm_width: i32,
+    transform: i32,
+    scale: i32,
+    /* Got all the information at least once */
+    done: bool,
+    /* The compositor closed our bar on this, don't try again until it changes */
+    closed: bool,
+}
+
+impl Output {
+    /* The size with the transform applied */
+    fn get_size(&self) -> (i32, i32) {
+        if self.transform % 2 == 1 {
+            return (self.height, self.width);
+        }
+        return (self.width, self.height);
+    }
+
+    /* The HiDPI scale, like xorg gets it for monitors */
+    fn get_scale(&self, conf: &config::Config) -> f64 {
+        /* The mode and the physical size are both from before the transform */
+        return conf.get_scale(&self.name, self.width as u32, self.mm_width as u32);
+    }
+}
+
+struct Buffer {
+    id: u32,
+    pool: u32,
+    file: File,
+    width: u32,
+    height: u32,
+    /* The compositor still reads from it */
+    busy: bool,
+}
+
+struct Bar {
+    /* The global name of the output the bar is on */
+    output: u32,
+    surface: u32,
+    layer: u32,
+    /* In surface coordinates, as the compositor configured it */
+    width: u32,
+    height: u32,
+    /* The output scale, buffers are this much larger than the surface */
+    buffer_scale: i32,
+    /* Scale for drawing, from the config */
+    scale: f64,
+    configured: bool,
+    buffers: Vec<Buffer>,
+}
+
+/// The connection to a compositor, with the globals the bars need bound
+pub struct Wayland {
+    conn: Connection,
+    objects: HashMap<u32, Object>,
+    registry: u32,
+    compositor: u32,
+    shm: u32,
+    layer_shell: u32,
+    xdg_output_manager: u32,
+    outputs: Vec<Output>,
+    bars: Vec<Bar>,
+    /* For the file names of shm pools */
+    pool_serial: u32,
+    /* A sync callback is still outstanding */
+    syncing: bool,
+    /* The outputs changed since bars were last synced */
+    outputs_changed: bool,
+}
+
+impl Wayland {
+    fn new(conn: Connection) -> Self {
+        return Wayland { conn: conn, objects: HashMap::new(),
+                         registry: 0, compositor: 0, shm: 0, layer_shell: 0,
+                         xdg_output_manager: 0,
+                         outputs: Vec::new(), bars: Vec::new(),
+                         pool_serial: 0, syncing: false, outputs_changed: false };
+    }
+
+    fn bind(&mut self, name: u32, interface: &str, version: u32, obj: Object) -> u32 {
+        let id = self.conn.new_id();
+        let registry = self.registry;
+        self.conn.send(registry, 0, &[Arg::Uint(name), Arg::Str(interface),
+                                      Arg::Uint(version), Arg::Uint(id)]);
+        self.objects.insert(id, obj);
+        return id;
+    }
+
+    fn bind_output(&mut self, name: u32, version: u32) {
+        /* name and description came with version 4 */
+        let version = std::cmp::min(version, 4);
+        let id = self.bind(name, "wl_output", version, Object::Output(name));
+        self.outputs.push(Output { global: name, id: id, version: version, xdg: 0,
+                                   name: String::new(), x: 0, y: 0,
+                                   width: 0, height: 0, mm_width: 0,
+                                   transform: 0, scale: 1,
+                                   done: false, closed: false });
+
+        if self.xdg_output_manager != 0 {
+            let i = self.outputs.len() - 1;
+            self.get_xdg_output(i);
+        }
+    }
+
+    /* Ask for the name of outputs that can't tell it themselves */
+    fn get_xdg_output(&mut self, i: usize) {
+        if self.outputs[i].version >= 4 || self.outputs[i].xdg != 0 {
+            return;
+        }
+
+        let id = self.conn.new_id();
+        let (manager, output, global) = (self.xdg_output_manager, self.outputs[i].id, self.outputs[i].global);
+        self.conn.send(manager, 1, &[Arg::Uint(id), Arg::Uint(output)]);
+        self.objects.insert(id, Object::XdgOutput(global));
+        self.outputs[i].xdg = id;
+    }
+
+    /* Wait until the compositor handled everything we sent so far.
+     * Only handles events, the bars are left alone */
+    fn roundtrip(&mut self) {
+        let id = self.conn.new_id();
+        self.conn.send(DISPLAY_ID, 0, &[Arg::Uint(id)]);
+        self.objects.insert(id, Object::Callback);
+        self.conn.flush();
+
+        self.syncing = true;
+        while self.syncing && self.conn.error.is_none() {
+            self.conn.read();
+            while let Some((obj, opcode, data)) = self.conn.next_event() {
+                self.handle_event(obj, opcode, &data);
+            }
+        }
+    }
+
+    /* Handle all complete events. Returns true if the bars need a redraw */
+    fn dispatch(&mut self, conf: &config::Config) -> bool {
+        let mut redraw = false;
+        while let Some((obj, opcode, data)) = self.conn.next_event() {
+            redraw |= self.handle_event(obj, opcode, &data);
+        }
+
+        if self.outputs_changed {
+            redraw |= self.sync_bars(conf);
+        }
+
+        self.conn.flush();
+        return redraw;
+    }
+
+    fn handle_event(&mut self, obj: u32, opcode: u16, data: &[u8]) -> bool {
+        let mut args = Args::new(data);
+
+        if obj == DISPLAY_ID {
+            if opcode == 0 {
+                let object = args.uint();
+                let code = args.uint();
+                let message = args.string();
+                /* Protocol errors are fatal, the compositor hangs up after this */
+                self.conn.fail(format!("Compositor error on object {} ({}): {}", object, code, message));
+            }
+            /* delete_id, ids aren't reused so there's nothing to do */
+            return false;
+        }
+
+        match self.objects.get(&obj) {
+            Some(&Object::Registry) => {
+                if opcode == 0 {
+                    let name = args.uint();
+                    let interface = args.string();
+                    let version = args.uint();
+                    match interface.as_str() {
+                        "wl_compositor" => self.compositor = self.bind(name, "wl_compositor", std::cmp::min(version, 4), Object::Compositor),
+                        "wl_shm" => self.shm = self.bind(name, "wl_shm", 1, Object::Shm),
+                        "zwlr_layer_shell_v1" => self.layer_shell = self.bind(name, "zwlr_layer_shell_v1", 1, Object::LayerShell),
+                        "wl_output" => self.bind_output(name, version),
+                        "zxdg_output_manager_v1" => {
+                            self.xdg_output_manager = self.bind(name, "zxdg_output_manager_v1",
+                                                                std::cmp::min(version, 3), Object::XdgOutputManager);
+                            for i in 0..self.outputs.len() {
+                                self.get_xdg_output(i);
+                            }
+                        },
+                        _ => {},
+                    }
+                } else if opcode == 1 {
+                    let name = args.uint();
+                    self.remove_output(name);
+                }
+                return false;
+            },
+            Some(&Object::Callback) => {
+                self.objects.remove(&obj);
+                self.syncing = false;
+                return false;
+            },
+            Some(&Object::Output(name)) => {
+                self.handle_output_event(name, opcode, &mut args);
+                return false;
+            },
+            Some(&Object::XdgOutput(name)) => {
+                /* name, the rest is in wl_output as well */
+                if opcode == 3 {
+                    if let Some(output) = self.outputs.iter_mut().find(|output| output.global == name) {
+                        output.name = args.string();
+                        /* Older versions may send this after wl_output's done */
+                        if output.done {
+                            self.outputs_changed = true;
+                        }
+                    }
+                }
+                return false;
+            },
+            Some(&Object::LayerSurface) => {
+                return self.handle_layer_event(obj, opcode, &mut args);
+            },
+            Some(&Object::Buffer) => {
+                /* release */
+                for bar in self.bars.iter_mut() {
+                    for buffer in bar.buffers.iter_mut().filter(|buffer| buffer.id == obj) {
+                        buffer.busy = false;
+                    }
+                }
+                return false;
+            },
+            /* Nothing else has events we care about */
+            _ => return false,
+        }
+    }
+
+    fn handle_output_event(&mut self, name: u32, opcode: u16, args: &mut Args) {
+        let output = match self.outputs.iter_mut().find(|output| output.global == name) {
+            Some(x) => x,
+            None => return,
+        };
+
+        match opcode {
+            /* geometry */
+            0 => {
+                output.x = args.int();
+                output.y = args.int();
+                output.mm_width = args.int();
+                let _ = args.int();
+                let _ = args.int();
+                let make = args.string();
+                let model = args.string();
+                output.transform = args.int();
+                /* Before version 4 there are no names. Without xdg-output
+                 * this is the best we get, and it won't match RandR names */
+                if output.version < 4 && output.name.is_empty() {
+                    output.name = format!("{} {}", make, model);
+                }
+            },
+            /* mode */
+            1 => {
+                let flags = args.uint();
+                let width = args.int();
+                let height = args.int();
+                /* Only the current mode is interesting */
+                if flags & 1 != 0 {
+                    output.width = width;
+                    output.height = height;
+                }
+            },
+            /* done */
+            2 => {
+                output.done = true;
+                output.closed = false;
+                self.outputs_changed = true;
+            },
+            /* scale */
+            3 => output.scale = std::cmp::max(args.int(), 1),
+            /* name */
+            4 => output.name = args.string(),
+            _ => {},
+        }
+    }
+
+    fn handle_layer_event(&mut self, obj: u32, opcode: u16, args: &mut Args) -> bool {
+        let i = match self.bars.iter().position(|bar| bar.layer == obj) {
+            Some(x) => x,
+            None => return false,
+        };
+
+        match opcode {
+            /* configure */
+            0 => {
+                let serial = args.uint();
+                let width = args.uint();
+                let height = args.uint();
+                self.conn.send(obj, 6, &[Arg::Uint(serial)]);
+
+                let bar = &mut self.bars[i];
+                if width > 0 {
+                    bar.width = width;
+                }
+                if height > 0 {
+                    bar.height = height;
+                }
+                bar.configured = true;
+                return true;
+            },
+            /* closed */
+            1 => {
+                let bar = self.bars.remove(i);
+                println!("The compositor closed the bar");
+                if let Some(output) = self.outputs.iter_mut().find(|output| output.global == bar.output) {
+                    output.closed = true;
+                }
+                self.destroy_bar(bar);
+                return false;
+            },
+            _ => return false,
+        }
+    }
+
+    fn remove_output(&mut self, name: u32) {
+        let i = match self.outputs.iter().position(|output| output.global == name) {
+            Some(x) => x,
+            None => return,
+        };
+
+        let output = self.outputs.remove(i);
+        println!("Output {} went away", output.name);
+        /* release only exists since version 3 */
+        if output.version >= 3 {
+            self.conn.send(output.id, 0, &[]);
+        }
+        if output.xdg != 0 {
+            self.conn.send(output.xdg, 0, &[]);
+            self.objects.remove(&output.xdg);
+        }
+        self.objects.remove(&output.id);
+        self.outputs_changed = true;
+    }
+
+    /* The outputs that should have a bar on them */
+    fn get_wanted(&self, pos: &config::Position) -> Vec<u32> {
+        let mut viables: Vec<&Output> = self.outputs.iter()
+            .filter(|output| output.done && !output.closed)
+            .filter(|output| match pos {
+                &config::Position::Monitor(ref name, _) => &output.name == name,
+                _ => true,
+            }).collect();
+
+        if let &config::Position::All(_) = pos {
+            return viables.into_iter().map(|output| output.global).collect();
+        }
+
+        viables.sort_by_key(|output| match pos.get_direction() {
+            &config::Direction::Top | &config::Direction::Bottom => output.y,
+            &config::Direction::Left | &config::Direction::Right => output.x,
+        });
+        return viables.into_iter().take(1).map(|output| output.global).collect();
+    }
+
+    /* How large the bar is across, in surface coordinates */
+    fn get_thickness(output: &Output, conf: &config::Config) -> u32 {
+        let (width, height) = output.get_size();
+        let across = match conf.position.get_direction() {
+            &config::Direction::Top | &config::Direction::Bottom => height,
+            &config::Direction::Left | &config::Direction::Right => width,
+        };
+        let pixels = conf.size.get_height(across, output.get_scale(conf));
+
+        return std::cmp::max((pixels + output.scale - 1) / output.scale, 1) as u32;
+    }
+
+    /* Tell the compositor where the bar should go */
+    fn place_bar(&mut self, layer: u32, thickness: u32, conf: &config::Config) {
+        let (anchor, width, height) = match conf.position.get_direction() {
+            &config::Direction::Top => (ANCHOR_TOP | ANCHOR_LEFT | ANCHOR_RIGHT, 0, thickness),
+            &config::Direction::Bottom => (ANCHOR_BOTTOM | ANCHOR_LEFT | ANCHOR_RIGHT, 0, thickness),
+            &config::Direction::Left => (ANCHOR_LEFT | ANCHOR_TOP | ANCHOR_BOTTOM, thickness, 0),
+            &config::Direction::Right => (ANCHOR_RIGHT | ANCHOR_TOP | ANCHOR_BOTTOM, thickness, 0),
+        };
+
+        self.conn.send(layer, 0, &[Arg::Uint(width), Arg::Uint(height)]);
+        self.conn.send(layer, 1, &[Arg::Uint(anchor)]);
+        /* The equivalent of the struts on X */
+        self.conn.send(layer, 2, &[Arg::Int(thickness as i32)]);
+    }
+
+    fn create_bar(&mut self, i: usize, conf: &config::Config) {
+        let thickness = Wayland::get_thickness(&self.outputs[i], conf);
+        let (global, output_id, buffer_scale, scale) = {
+            let output = &self.outputs[i];
+            (output.global, output.id, output.scale, output.get_scale(conf))
+        };
+        println!("Creating bar on {}", self.outputs[i].name);
+
+        let surface = self.conn.new_id();
+        let compositor = self.compositor;
+        self.conn.send(compositor, 0, &[Arg::Uint(surface)]);
+        self.objects.insert(surface, Object::Surface);
+        /* set_buffer_scale */
+        self.conn.send(surface, 8, &[Arg::Int(buffer_scale)]);
+
+        let layer = self.conn.new_id();
+        let shell = self.layer_shell;
+        self.conn.send(shell, 0, &[Arg::Uint(layer), Arg::Uint(surface), Arg::Uint(output_id),
+                                   Arg::Uint(LAYER_TOP), Arg::Str("ongybar")]);
+        self.objects.insert(layer, Object::LayerSurface);
+
+        self.place_bar(layer, thickness, conf);
+        /* The first commit without buffer gets us the configure */
+        self.conn.send(surface, 6, &[]);
+
+        self.bars.push(Bar { output: global, surface: surface, layer: layer,
+                             width: 0, height: 0, buffer_scale: buffer_scale, scale: scale,
+                             configured: false, buffers: Vec::new() });
+    }
+
+    fn destroy_buffer(&mut self, buffer: Buffer) {
+        self.conn.send(buffer.id, 0, &[]);
+        self.conn.send(buffer.pool, 1, &[]);
+        self.objects.remove(&buffer.id);
+        self.objects.remove(&buffer.pool);
+    }
+
+    fn destroy_bar(&mut self, mut bar: Bar) {
+        for buffer in bar.buffers.drain(..) {
+            self.destroy_buffer(buffer);
+        }
+        self.conn.send(bar.layer, 7, &[]);
+        self.conn.send(bar.surface, 0, &[]);
+        self.objects.remove(&bar.layer);
+        self.objects.remove(&bar.surface);
+    }
+
+    /* Make the bars match the outputs. Returns true if anything changed */
+    fn sync_bars(&mut self, conf: &config::Config) -> bool {
+        let wanted = self.get_wanted(&conf.position);
+        let mut updated = false;
+        self.outputs_changed = false;
+
+        let (keep, gone): (Vec<Bar>, Vec<Bar>) = self.bars.drain(..)
+            .partition(|bar| wanted.contains(&bar.output));
+        self.bars = keep;
+        for bar in gone {
+            println!("Removing a bar");
+            self.destroy_bar(bar);
+            updated = true;
+        }
+
+        for global in wanted {
+            let i = match self.outputs.iter().position(|output| output.global == global) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            match self.bars.iter().position(|bar| bar.output == global) {
+                Some(b) => {
+                    /* The mode or scale may have changed */
+                    let thickness = Wayland::get_thickness(&self.outputs[i], conf);
+                    let buffer_scale = self.outputs[i].scale;
+                    let scale = self.outputs[i].get_scale(conf);
+                    let (layer, surface) = (self.bars[b].layer, self.bars[b].surface);
+
+                    self.bars[b].buffer_scale = buffer_scale;
+                    self.bars[b].scale = scale;
+                    self.conn.send(surface, 8, &[Arg::Int(buffer_scale)]);
+                    self.place_bar(layer, thickness, conf);
+                    self.conn.send(surface, 6, &[]);
+                },
+                None => self.create_bar(i, conf),
+            }
+            updated = true;
+        }
+
+        return updated;
+    }
+
+    /* Get a buffer of the given size the compositor isn't using */
+    fn get_buffer(&mut self, b: usize, width: u32, height: u32) -> Option<usize> {
+        /* Anything of the wrong size won't be used again */
+        let (keep, old): (Vec<Buffer>, Vec<Buffer>) = self.bars[b].buffers.drain(..)
+            .partition(|buffer| buffer.busy || (buffer.width == width && buffer.height == height));
+        self.bars[b].buffers = keep;
+        for buffer in old {
+            self.destroy_buffer(buffer);
+        }
+
+        if let Some(i) = self.bars[b].buffers.iter().position(|buffer| !buffer.busy && buffer.width == width && buffer.height == height) {
+            return Some(i);
+        }
+
+        /* The pool is backed by a file that's gone from the file system
+         * as soon as the compositor has it */
+        let size = width * height * 4;
+        let dir = match std::env::var("XDG_RUNTIME_DIR") {
+            Ok(x) => PathBuf::from(x),
+            Err(_) => return None,
+        };
+        self.pool_serial += 1;
+        let path = dir.join(format!("ongybar-{}-{}", std::process::id(), self.pool_serial));
+        let file = match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+            Ok(x) => x,
+            Err(x) => {
+                println!("Couldn't create shm file {}: {}", path.display(), x);
+                return None;
+            },
+        };
+        let _ = std::fs::remove_file(&path);
+        if let Err(x) = file.set_len(size as u64) {
+            println!("Couldn't resize shm file: {}", x);
+            return None;
+        }
+
+        let pool = self.conn.new_id();
+        let shm = self.shm;
+        self.conn.send(shm, 0, &[Arg::Uint(pool), Arg::Fd(file.as_raw_fd()), Arg::Int(size as i32)]);
+        self.objects.insert(pool, Object::Pool);
+
+        let id = self.conn.new_id();
+        self.conn.send(pool, 0, &[Arg::Uint(id), Arg::Int(0), Arg::Int(width as i32),
+                                  Arg::Int(height as i32), Arg::Int(width as i32 * 4),
+                                  Arg::Uint(SHM_FORMAT_ARGB8888)]);
+        self.objects.insert(id, Object::Buffer);
+        /* The fd has to be sent before the file is closed */
+        self.conn.flush();
+
+        self.bars[b].buffers.push(Buffer { id: id, pool: pool, file: file,
+                                           width: width, height: height, busy: false });
+        return Some(self.bars[b].buffers.len() - 1);
+    }
+
+    fn draw_bar<F>(&mut self, b: usize, canvas: &mut Canvas, draw: &mut F)
+        where F: FnMut(&mut Canvas, &Frame) {
+        if !self.bars[b].configured {
+            return;
+        }
+
+        let buffer_scale = self.bars[b].buffer_scale as u32;
+        let width = self.bars[b].width * buffer_scale;
+        let height = self.bars[b].height * buffer_scale;
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        canvas.begin(width, height);
+        draw(canvas, &Frame { width: width, height: height, scale: self.bars[b].scale, root: None });
+
+        let i = match self.get_buffer(b, width, height) {
+            Some(x) => x,
+            None => return,
+        };
+
+        {
+            let buffer = &mut self.bars[b].buffers[i];
+            if let Err(x) = buffer.file.write_all_at(canvas.pixels().unwrap(), 0) {
+                println!("Couldn't write frame: {}", x);
+                return;
+            }
+            buffer.busy = true;
+        }
+
+        let surface = self.bars[b].surface;
+        let id = self.bars[b].buffers[i].id;
+        let (bar_width, bar_height) = (self.bars[b].width, self.bars[b].height);
+        self.conn.send(surface, 1, &[Arg::Uint(id), Arg::Int(0), Arg::Int(0)]);
+        self.conn.send(surface, 2, &[Arg::Int(0), Arg::Int(0),
+                                     Arg::Int(bar_width as i32), Arg::Int(bar_height as i32)]);
+        self.conn.send(surface, 6, &[]);
+    }
+}
+
+/* Bind the globals the bars need. Fails if the compositor doesn't have them */
+fn setup(conn: Connection) -> Result<Wayland, String> {
+    let mut wl = Wayland::new(conn);
+
+    wl.registry = wl.conn.new_id();
+    let registry = wl.registry;
+    wl.conn.send(DISPLAY_ID, 1, &[Arg::Uint(registry)]);
+    wl.objects.insert(registry, Object::Registry);
+    /* Once for the globals, once for what we bound */
+    wl.roundtrip();
+    wl.roundtrip();
+
+    if let Some(ref x) = wl.conn.error {
+        return Err(x.clone());
+    }
+    if wl.compositor == 0 || wl.shm == 0 {
+        return Err(String::from("The compositor is missing wl_compositor or wl_shm"));
+    }
+    if wl.layer_shell == 0 {
+        return Err(String::from("The compositor doesn't support wlr-layer-shell, can't place the bar"));
+    }
+
+    return Ok(wl);
+}
+
+/// Connect to the compositor and check that the bar can be placed on it
+pub fn connect() -> Result<Wayland, String> {
+    return Connection::connect().and_then(setup);
+}
+
+/// Bars as layer surfaces on a wayland compositor. Always renders in software
+pub struct WaylandBackend<'a> {
+    wl: Wayland,
+    canvas: Canvas,
+    conf: &'a config::Config,
+}
+
+impl<'a> WaylandBackend<'a> {
+    /// Set up the bars on a compositor from connect
+    pub fn new(conf: &'a config::Config, wl: Wayland) -> Self {
+        let mut wl = wl;
+
+        if let config::BackgroundMode::Root = conf.background_mode {
+            println!("There's no root window on wayland, only drawing the background colour");
+        }
+
+        wl.sync_bars(conf);
+        wl.conn.flush();
+        /* From here on events are only read when the event loop says so */
+        wl.conn.socket.set_nonblocking(true).unwrap();
+
+        return WaylandBackend { wl: wl, canvas: Canvas::new(), conf: conf };
+    }
+}
+
+impl<'a> Backend for WaylandBackend<'a> {
+    type Surface = Canvas;
+
+    fn get_fds(&self) -> Vec<c_int> {
+        return vec![self.wl.conn.socket.as_raw_fd()];
+    }
+
+    fn handle_events(&mut self) -> bool {
+        while self.wl.conn.read() {
+        }
+
+        if let Some(ref x) = self.wl.conn.error {
+            println!("{}", x);
+            return false;
+        }
+
+        return self.wl.dispatch(self.conf);
+    }
+
+    fn is_running(&self) -> bool {
+        return self.wl.conn.error.is_none();
+    }
+
+    fn present<F>(&mut self, draw: &mut F)
+        where F: FnMut(&mut Canvas, &Frame) {
+        for b in 0..self.wl.bars.len() {
+            self.wl.draw_bar(b, &mut self.canvas, draw);
+        }
+        self.wl.conn.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::thread;
+
+    /* Answers syncs and hands out the given globals, ignores everything else.
+     * With noise it also sends events nobody knows */
+    fn fake_compositor(socket: UnixStream, globals: &'static [(&'static str, u32)], noise: bool) -> thread::JoinHandle<()> {
+        return thread::spawn(move || {
+            let mut conn = Connection::new(socket);
+            while conn.read() {
+                while let Some((obj, opcode, data)) = conn.next_event() {
+                    let mut args = Args::new(&data);
+                    if obj == DISPLAY_ID && opcode == 0 {
+                        let callback = args.uint();
+                        conn.send(callback, 0, &[Arg::Uint(0)]);
+                    } else if obj == DISPLAY_ID && opcode == 1 {
+                        let registry = args.uint();
+                        if noise {
+                            conn.send(DISPLAY_ID, 7, &[Arg::Uint(1)]);
+                            conn.send(registry, 9, &[Arg::Str("nothing"), Arg::Int(-1)]);
+                            conn.send(1000, 0, &[Arg::Uint(3)]);
+                        }
+                        for (i, &(interface, version)) in globals.iter().enumerate() {
+                            conn.send(registry, 0, &[Arg::Uint(i as u32 + 1), Arg::Str(interface), Arg::Uint(version)]);
+                        }
+                    }
+                }
+                conn.flush();
+            }
+        });
+    }
+
+    fn event(obj: u32, opcode: u16, args: &[Arg]) -> Vec<u8> {
+        let (mut out, mut fds) = (Vec::new(), Vec::new());
+        marshal(&mut out, &mut fds, obj, opcode, args);
+        return out[8..].to_vec();
+    }
+
+    #[test]
+    fn marshal_pads_strings() {
+        let (mut out, mut fds) = (Vec::new(), Vec::new());
+        marshal(&mut out, &mut fds, 2, 0, &[Arg::Uint(7), Arg::Str("wl_shm"), Arg::Uint(1), Arg::Uint(5)]);
+
+        let words: Vec<u32> = out.chunks(4).map(read_u32).collect();
+        assert_eq!(out.len(), 32);
+        assert_eq!(&words[..4], &[2, 32 << 16, 7, 7]);
+        assert_eq!(&out[16..24], b"wl_shm\0\0");
+        assert_eq!(&words[6..], &[1, 5]);
+        assert!(fds.is_empty());
+
+        /* The terminating 0 fills the last word, no padding after it */
+        out.clear();
+        marshal(&mut out, &mut fds, 3, 4, &[Arg::Str("abc"), Arg::Int(-1)]);
+        assert_eq!(out.len(), 20);
+        assert_eq!(read_u32(&out[4..]), 20 << 16 | 4);
+        assert_eq!(&out[8..16], &[4, 0, 0, 0, b'a', b'b', b'c', 0]);
+        assert_eq!(read_u32(&out[16..]), 0xffffffff);
+    }
+
+    #[test]
+    fn args_skip_padding() {
+        let data = event(1, 0, &[Arg::Str("DP-1"), Arg::Int(-20), Arg::Str(""), Arg::Uint(3)]);
+        let mut args = Args::new(&data);
+        assert_eq!(args.string(), "DP-1");
+        assert_eq!(args.int(), -20);
+        assert_eq!(args.string(), "");
+        assert_eq!(args.uint(), 3);
+        /* Running past the end doesn't panic */
+        assert_eq!(args.uint(), 0);
+        assert_eq!(args.string(), "");
+    }
+
+    #[test]
+    fn events_wait_until_complete() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut conn = Connection::new(a);
+        let mut fds = Vec::new();
+        marshal(&mut conn.input, &mut fds, 5, 2, &[Arg::Uint(1), Arg::Str("name")]);
+        marshal(&mut conn.input, &mut fds, 6, 0, &[]);
+        let rest = conn.input.split_off(13);
+
+        assert!(conn.next_event().is_none());
+        conn.input.extend_from_slice(&rest);
+        let (obj, opcode, data) = conn.next_event().unwrap();
+        assert_eq!((obj, opcode), (5, 2));
+        assert_eq!(Args::new(&data).uint(), 1);
+        assert_eq!(conn.next_event(), Some((6, 0, Vec::new())));
+        assert!(conn.next_event().is_none());
+    }
+
+    #[test]
+    fn fds_are_passed() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let file = File::open("/dev/null").unwrap();
+        let mut conn = Connection::new(a);
+        conn.send(4, 0, &[Arg::Uint(9), Arg::Fd(file.as_raw_fd()), Arg::Uint(10)]);
+        conn.flush();
+        assert!(conn.error.is_none());
+
+        let mut data = [0u8; 64];
+        let mut control = [0u64; 8];
+        let (len, fd) = unsafe {
+            let mut iov = libc::iovec { iov_base: data.as_mut_ptr() as *mut c_void, iov_len: data.len() };
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut c_void;
+            msg.msg_controllen = std::mem::size_of_val(&control) as _;
+            let len = libc::recvmsg(b.as_raw_fd(), &mut msg, 0);
+
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            assert!(!cmsg.is_null());
+            assert_eq!(((*cmsg).cmsg_level, (*cmsg).cmsg_type), (libc::SOL_SOCKET, libc::SCM_RIGHTS));
+            (len, *(libc::CMSG_DATA(cmsg) as *const c_int))
+        };
+
+        /* The fd isn't part of the data */
+        assert_eq!(len, 16);
+        assert_eq!(read_u32(&data[4..]), 16 << 16);
+        assert_eq!((read_u32(&data[8..]), read_u32(&data[12..])), (9, 10));
+        assert!(fd != file.as_raw_fd());
+        assert!(unsafe { libc::fcntl(fd, libc::F_GETFD) } >= 0);
+        unsafe { libc::close(fd) };
+    }
+
+    #[test]
+    fn hangup_is_an_error() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut conn = Connection::new(a);
+        drop(b);
+
+        assert!(!conn.read());
+        assert!(conn.error.is_some());
+        /* Nothing is queued once the connection is gone */
+        conn.send(1, 0, &[Arg::Uint(2)]);
+        assert!(conn.out.is_empty());
+    }
+
+    #[test]
+    fn setup_needs_layer_shell() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let compositor = fake_compositor(b, &[("wl_compositor", 4), ("wl_shm", 1), ("wl_output", 3)], false);
+        match setup(Connection::new(a)) {
+            Ok(_) => panic!("Set up without layer-shell"),
+            Err(x) => assert!(x.contains("layer-shell")),
+        }
+        compositor.join().unwrap();
+
+        let (a, b) = UnixStream::pair().unwrap();
+        let compositor = fake_compositor(b, &[("wl_compositor", 4), ("zwlr_layer_shell_v1", 1), ("wl_shm", 1)], false);
+        let wl = setup(Connection::new(a)).ok().expect("Couldn't set up with layer-shell");
+        assert!(wl.compositor != 0 && wl.shm != 0 && wl.layer_shell != 0);
+        drop(wl);
+        compositor.join().unwrap();
+    }
+
+    #[test]
+    fn unknown_events_are_ignored() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let compositor = fake_compositor(b, &[("wl_compositor", 4), ("zwlr_layer_shell_v1", 1), ("wl_shm", 1)], true);
+        let wl = setup(Connection::new(a)).ok().expect("Unknown events broke the setup");
+        assert!(wl.layer_shell != 0);
+        drop(wl);
+        compositor.join().unwrap();
+    }
+
+    #[test]
+    fn hangup_in_the_middle_of_a_message() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let compositor = thread::spawn(move || {
+            let mut conn = Connection::new(b.try_clone().unwrap());
+            while conn.read() {
+                if let Some((DISPLAY_ID, 1, data)) = conn.next_event() {
+                    let registry = Args::new(&data).uint();
+                    let (mut out, mut fds) = (Vec::new(), Vec::new());
+                    marshal(&mut out, &mut fds, registry, 0, &[Arg::Uint(1), Arg::Str("wl_compositor"), Arg::Uint(4)]);
+                    (&b).write_all(&out[..10]).unwrap();
+                    return;
+                }
+            }
+        });
+
+        match setup(Connection::new(a)) {
+            Ok(_) => panic!("Set up with half a message"),
+            Err(x) => assert!(x.contains("closed")),
+        }
+        compositor.join().unwrap();
+    }
+
+    #[test]
+    fn broken_headers_are_errors() {
+        for &size in &[0u32, 4, 4100] {
+            let (a, _b) = UnixStream::pair().unwrap();
+            let mut conn = Connection::new(a);
+            push_u32(&mut conn.input, 3);
+            push_u32(&mut conn.input, size << 16);
+            conn.input.extend_from_slice(&[0; 8]);
+
+            assert!(conn.next_event().is_none());
+            assert!(conn.error.is_some());
+            assert!(conn.input.is_empty());
+        }
+    }
+
+    #[test]
+    fn short_events_dont_panic() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut wl = Wayland::new(Connection::new(a));
+        wl.registry = wl.conn.new_id();
+        wl.objects.insert(wl.registry, Object::Registry);
+        let registry = wl.registry;
+        wl.handle_event(registry, 0, &event(0, 0, &[Arg::Uint(20), Arg::Str("wl_output"), Arg::Uint(3)]));
+        wl.handle_event(registry, 0, &event(0, 0, &[Arg::Uint(21), Arg::Str("zxdg_output_manager_v1"), Arg::Uint(3)]));
+        let (output, xdg) = (wl.outputs[0].id, wl.outputs[0].xdg);
+
+        /* Lengths that run past the end, and arguments cut off anywhere */
+        let mut garbage = vec![0xff; 8];
+        garbage.extend(event(0, 0, &[Arg::Uint(5), Arg::Str("wl_output"), Arg::Uint(0x7ffffffc), Arg::Int(2)]));
+        for opcode in 0..8 {
+            for &obj in &[output, xdg, 1000, registry, DISPLAY_ID] {
+                for len in 0..garbage.len() + 1 {
+                    wl.handle_event(obj, opcode, &garbage[..len]);
+                    wl.handle_event(obj, opcode, &garbage[8..std::cmp::max(len, 8)]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn old_outputs_are_named_by_xdg_output() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut wl = Wayland::new(Connection::new(a));
+        wl.registry = wl.conn.new_id();
+        wl.objects.insert(wl.registry, Object::Registry);
+        let registry = wl.registry;
+
+        wl.handle_event(registry, 0, &event(0, 0, &[Arg::Uint(20), Arg::Str("wl_output"), Arg::Uint(3)]));
+        wl.handle_event(registry, 0, &event(0, 0, &[Arg::Uint(21), Arg::Str("zxdg_output_manager_v1"), Arg::Uint(3)]));
+        let (id, xdg) = (wl.outputs[0].id, wl.outputs[0].xdg);
+        assert!(xdg != 0);
+
+        wl.handle_event(id, 0, &event(0, 0, &[Arg::Int(0), Arg::Int(0), Arg::Int(600), Arg::Int(340),
+                                             Arg::Int(0), Arg::Str("Dell"), Arg::Str("U2715H"), Arg::Int(1)]));
+        wl.handle_event(id, 1, &event(0, 0, &[Arg::Uint(1), Arg::Int(2560), Arg::Int(1440), Arg::Int(60000)]));
+        wl.handle_event(id, 2, &[]);
+        let pos = config::Position::Monitor(String::from("DP-1"), config::Direction::Top);
+        assert!(wl.get_wanted(&pos).is_empty());
+
+        wl.outputs_changed = false;
+        wl.handle_event(xdg, 3, &event(0, 0, &[Arg::Str("DP-1")]));
+        assert!(wl.outputs_changed);
+        assert_eq!(wl.get_wanted(&pos), vec![20]);
+
+        /* Geometry comes again on changes, that doesn't lose the name */
+        wl.handle_event(id, 0, &event(0, 0, &[Arg::Int(0), Arg::Int(0), Arg::Int(600), Arg::Int(340),
+                                             Arg::Int(0), Arg::Str("Dell"), Arg::Str("U2715H"), Arg::Int(0)]));
+        assert_eq!(wl.get_wanted(&pos), vec![20]);
+
+        wl.handle_event(registry, 1, &event(0, 0, &[Arg::Uint(20)]));
+        assert!(wl.outputs.is_empty());
+        assert!(!wl.objects.contains_key(&xdg));
+    }
+}
@@ -1,4 +1,3 @@
-extern crate mio;
 extern crate x11;
 extern crate xcb;
 extern crate gl;
@@ -6,19 +5,15 @@ extern crate libc;
 extern crate hostname;
 
 use ::config;
+use backend::{Backend, Frame, RootImage};
+use surface::Surface;
 
-use self::mio::*;
 use self::x11::glx::*;
 use self::x11::xlib;
 use self::xcb::dri2;
 
 use std;
-use std::borrow::Borrow;
-use std::boxed::Box;
-use std::cell::RefCell;
-use std::collections::HashMap;
 use std::ffi::{CStr, CString};
-use std::ops::DerefMut;
 use std::os::raw::*;
 use std::os::raw::{c_int, c_void};
 use std::ptr::null_mut;
@@ -35,31 +30,22 @@ struct Monitor {
     y: i16,
     width: u16,
     height: u16,
+    /* HiDPI scale, from the config or the physical size of the output */
+    scale: f64,
 }
 
 impl Monitor {
     // TODO: Can this type be sanitized?
-    fn from_crtc(name: String, arg: xcb::Reply<xcb::ffi::randr::xcb_randr_get_crtc_info_reply_t>) -> Self {
+    fn from_crtc(name: String, arg: xcb::Reply<xcb::ffi::randr::xcb_randr_get_crtc_info_reply_t>,
+                 scale: f64) -> Self {
         Monitor{ name: name,
                  x: arg.x(), y: arg.y(),
-                 width: arg.width(), height: arg.height() }
-    }
-
-    fn from_change(name: String, arg: xcb::randr::CrtcChange) -> Self {
-        Monitor{ name: name,
-                 x: arg.x(), y: arg.y(),
-                 width: arg.width(), height: arg.height() }
-    }
-
-    fn update_crtc(&mut self, arg: xcb::randr::CrtcChange) {
-         self.x = arg.x();
-         self.y = arg.y();
-         self.width = arg.width();
-         self.height = arg.height();
+                 width: arg.width(), height: arg.height(),
+                 scale: scale }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 struct Geometry {
     x: i16,
     y: i16,
@@ -77,10 +63,10 @@ impl Geometry {
                 x: mon.x,
                 y: mon.y,
                 width: mon.width,
-                height: size.get_height(mon.height as i32) as u16,
+                height: size.get_height(mon.height as i32, mon.scale) as u16,
             },
             &config::Direction::Bottom => {
-                let height = size.get_height(mon.height as i32) as u16;
+                let height = size.get_height(mon.height as i32, mon.scale) as u16;
                 Geometry {
                 x: mon.x,
                 y: mon.y + mon.height as i16 - height as i16,
@@ -88,26 +74,68 @@ impl Geometry {
                 height: height,
             }
             },
-            &config::Direction::Left   => unimplemented!(),
-            &config::Direction::Right  => unimplemented!(),
+            &config::Direction::Left   => Geometry {
+                x: mon.x,
+                y: mon.y,
+                width: size.get_height(mon.width as i32, mon.scale) as u16,
+                height: mon.height,
+            },
+            &config::Direction::Right  => {
+                let width = size.get_height(mon.width as i32, mon.scale) as u16;
+                Geometry {
+                x: mon.x + mon.width as i16 - width as i16,
+                y: mon.y,
+                width: width,
+                height: mon.height,
+            }
+            },
         }
     }
 }
 
-struct X11Window {
-    conn: xcb::Connection,
+/// A single bar window
+struct X11Bar {
     win: c_uint,
+    /* The name of the monitor the bar is on */
+    mon: String,
+    x: i32,
+    y: i32,
     width: u32,
     height: u32,
+    /* Bars are unmapped while their monitor is gone */
+    mapped: bool,
+    /* Only set with BackgroundMode::Root */
+    root_image: Option<RootImage>,
+}
+
+struct X11Window {
+    conn: xcb::Connection,
+    root: u32,
+    /* Never mapped, only used to get a GL context before there are bars */
+    leader: c_uint,
+    visual: u32,
+    depth: u8,
+    /* The visual has an alpha channel a compositor can use */
+    transparent: bool,
+    gc: u32,
 
     screen_num: i32,
     wm_delete_window: u32,
     wm_protocols: u32,
-    dri2_ev: u8,
+    /* Only set up for OpenGL */
+    dri2_ev: Option<u8>,
     randr_ev: u8,
     cmap: u32,
 
     mons: Vec<Monitor>,
+    bars: Vec<X11Bar>,
+
+    /* _XROOTPMAP_ID and ESETROOT_PMAP_ID, the properties wallpaper setters use */
+    root_pmap_atoms: [u32; 2],
+    root_serial: u32,
+
+    /* Cleared when the window gets closed */
+    running: bool,
 }
 
 type GlXCreateContextAttribsARBProc =
@@ -179,6 +207,40 @@ fn get_glxfbconfig(dpy: *mut xlib::Display, screen_num: i32,
     }
 }
 
+/* Like get_glxfbconfig, but only accept configs with a 32 bit visual.
+ * Those are the ones with an alpha channel the X server knows about */
+fn get_argb_glxfbconfig(dpy: *mut xlib::Display, screen_num: i32,
+        visual_attribs: &[i32]) -> Option<GLXFBConfig> {
+    unsafe {
+        let mut fbcount: c_int = 0;
+        let fbcs = glXChooseFBConfig(dpy, screen_num,
+                visual_attribs.as_ptr(),
+                &mut fbcount as *mut c_int);
+
+        let mut ret = None;
+        for i in 0..fbcount as isize {
+            let fbc = *fbcs.offset(i);
+            let vi = glXGetVisualFromFBConfig(dpy, fbc);
+            if vi.is_null() {
+                continue;
+            }
+
+            let depth = (*vi).depth;
+            xlib::XFree(vi as *mut c_void);
+
+            if depth == 32 {
+                ret = Some(fbc);
+                break;
+            }
+        }
+
+        if !fbcs.is_null() {
+            xlib::XFree(fbcs as *mut c_void);
+        }
+        ret
+    }
+}
+
 unsafe fn modify_atom(conn: &xcb::Connection, win: c_uint, mode: u8, name: &str, val: &str) {
     let atom = xcb::intern_atom(conn, false, name).get_reply().unwrap().atom();
     let value = [xcb::intern_atom(conn, false, val).get_reply().unwrap().atom()];
@@ -225,21 +287,69 @@ unsafe fn set_dock(conn: &xcb::Connection, win: c_uint) {
                          xcb::ATOM_CARDINAL, 32, &val);
 }
 
-unsafe fn set_struts(conn: &xcb::Connection, win: c_uint, x: i16, _: i16, width: u16, height: u16) {
+/* Reserve the edge of the screen the bar is docked to.
+ *
+ * Struts are relative to the edges of the whole X screen, not the monitor,
+ * so a bar on a monitor that doesn't touch the screen edge has to reserve
+ * everything between the edge and its far side. The start/end values limit
+ * that to the range the bar actually covers.
+ */
+unsafe fn set_struts(conn: &xcb::Connection, win: c_uint, root: u32,
+                     direction: &config::Direction,
+                     x: i16, y: i16, width: u16, height: u16) {
+    let (screen_width, screen_height) = match xcb::get_geometry(conn, root).get_reply() {
+        Ok(x) => (x.width() as u32, x.height() as u32),
+        Err(_) => {
+            println!("Couldn't get the screen size, not setting struts");
+            return;
+        }
+    };
+
+    let left = x as u32;
+    let top = y as u32;
+    let right = left + width as u32;
+    let bottom = top + height as u32;
+
+    /* LEFT RIGHT TOP BOTTOM */
+    let mut strut = [0; 4];
+    /* start/end pairs for LEFT RIGHT TOP BOTTOM, ends are inclusive */
+    let mut ranges = [0; 8];
+    match direction {
+        &config::Direction::Left => {
+            strut[0] = right;
+            ranges[0] = top;
+            ranges[1] = bottom.saturating_sub(1);
+        },
+        &config::Direction::Right => {
+            strut[1] = screen_width.saturating_sub(left);
+            ranges[2] = top;
+            ranges[3] = bottom.saturating_sub(1);
+        },
+        &config::Direction::Top => {
+            strut[2] = bottom;
+            ranges[4] = left;
+            ranges[5] = right.saturating_sub(1);
+        },
+        &config::Direction::Bottom => {
+            strut[3] = screen_height.saturating_sub(top);
+            ranges[6] = left;
+            ranges[7] = right.saturating_sub(1);
+        },
+    }
+
     {
         let prop = xcb::intern_atom(conn, false, "_NET_WM_STRUT").get_reply().unwrap().atom();
-        let value = [0, 0, height as u32, 0];
 
-        xcb::change_property(conn, xcb::PROP_MODE_REPLACE as u8, win, prop, xcb::ATOM_CARDINAL, 32, &value);
+        xcb::change_property(conn, xcb::PROP_MODE_REPLACE as u8, win, prop, xcb::ATOM_CARDINAL, 32, &strut);
     }
 
     {
         let prop = xcb::intern_atom(conn, false, "_NET_WM_STRUT_PARTIAL").get_reply().unwrap().atom();
-        let value = [0, 0, height as u32, 0,  /* LEFT RIGHT TOP BOTTOM */
-                     0, 0, /* LEFT */
-                     0, 0, /* RIGHT */
-                     x as u32, x as u32 + width as u32, /* TOP */
-                     0, 0 /* BOTTOM */
+        let value = [strut[0], strut[1], strut[2], strut[3],
+                     ranges[0], ranges[1], /* LEFT */
+                     ranges[2], ranges[3], /* RIGHT */
+                     ranges[4], ranges[5], /* TOP */
+                     ranges[6], ranges[7]  /* BOTTOM */
                     ];
 
         xcb::change_property(conn, xcb::PROP_MODE_REPLACE as u8, win, prop, xcb::ATOM_CARDINAL, 32, &value);
@@ -247,17 +357,131 @@ unsafe fn set_struts(conn: &xcb::Connection, win: c_uint, x: i16, _: i16, width:
 
 }
 
-unsafe fn set_geometry(win: &mut X11Window, x: i32, y: i32, width: u32, height: u32) {
-    let values = [(xlib::CWX, x as u32),
-                  (xlib::CWY, y as u32),
-                  (xlib::CWWidth, width),
-                  (xlib::CWHeight, height)
+unsafe fn clear_struts(conn: &xcb::Connection, win: c_uint) {
+    for name in ["_NET_WM_STRUT", "_NET_WM_STRUT_PARTIAL"].iter() {
+        let prop = xcb::intern_atom(conn, false, name).get_reply().unwrap().atom();
+        xcb::delete_property(conn, win, prop);
+    }
+}
+
+/* Get the pixmap the wallpaper setter put on the root window, if any */
+unsafe fn get_root_pixmap(win: &X11Window) -> Option<u32> {
+    for atom in win.root_pmap_atoms.iter() {
+        let reply = match xcb::get_property(&win.conn, false, win.root, *atom,
+                                            xcb::ATOM_PIXMAP, 0, 1).get_reply() {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+
+        if reply.format() != 32 || reply.value_len() != 1 {
+            continue;
+        }
+
+        let value: &[u32] = reply.value();
+        if value[0] != 0 {
+            return Some(value[0]);
+        }
+    }
+
+    return None;
+}
+
+/* Copy the area of the pixmap that's covered by bar. Whatever is outside of
+ * the pixmap stays black */
+unsafe fn grab_root_image(win: &X11Window, pixmap: u32,
+                          bar: &X11Bar, serial: u32) -> Option<RootImage> {
+    let (pix_width, pix_height) = match xcb::get_geometry(&win.conn, pixmap).get_reply() {
+        Ok(x) => (x.width() as i32, x.height() as i32),
+        Err(_) => {
+            println!("The root pixmap is gone, can't get the background");
+            return None;
+        }
+    };
+
+    let width = bar.width as i32;
+    let height = bar.height as i32;
+    let mut data = vec![0u8; (width * height * 4) as usize];
+    for pixel in data.chunks_mut(4) {
+        pixel[3] = 255;
+    }
+
+    let x0 = std::cmp::max(bar.x, 0);
+    let y0 = std::cmp::max(bar.y, 0);
+    let x1 = std::cmp::min(bar.x + width, pix_width);
+    let y1 = std::cmp::min(bar.y + height, pix_height);
+
+    if x1 > x0 && y1 > y0 {
+        let reply = match xcb::get_image(&win.conn, xcb::IMAGE_FORMAT_Z_PIXMAP as u8, pixmap,
+                                         x0 as i16, y0 as i16,
+                                         (x1 - x0) as u16, (y1 - y0) as u16,
+                                         !0).get_reply() {
+            Ok(x) => x,
+            Err(_) => {
+                println!("Couldn't read the root pixmap");
+                return None;
+            }
+        };
+
+        /* Those are stored with 32 bits per pixel, which is all we handle */
+        if reply.depth() != 24 && reply.depth() != 32 {
+            println!("Root pixmap has unsupported depth {}", reply.depth());
+            return None;
+        }
+
+        let src = reply.data();
+        let rows = (y1 - y0) as usize;
+        let cols = (x1 - x0) as usize;
+        let stride = src.len() / rows;
+        for row in 0..rows {
+            for col in 0..cols {
+                let s = row * stride + col * 4;
+                let d = (((y0 - bar.y) as usize + row) * width as usize
+                         + (x0 - bar.x) as usize + col) * 4;
+                /* The server hands out BGRX */
+                data[d] = src[s + 2];
+                data[d + 1] = src[s + 1];
+                data[d + 2] = src[s];
+            }
+        }
+    }
+
+    return Some(RootImage { bar: bar.win, serial: serial,
+                            width: bar.width, height: bar.height,
+                            data: data });
+}
+
+/* Take a fresh copy of the wallpaper behind every bar */
+unsafe fn update_root_images(win: &mut X11Window) {
+    let pixmap = get_root_pixmap(win);
+    if pixmap.is_none() {
+        println!("There's no root pixmap, only drawing the background colour");
+    }
+
+    /* All bars share the serial, so images from earlier grabs can be told apart */
+    win.root_serial = win.root_serial.wrapping_add(1);
+    for i in 0..win.bars.len() {
+        let image = match pixmap {
+            Some(x) => grab_root_image(win, x, &win.bars[i], win.root_serial),
+            None => None,
+        };
+        win.bars[i].root_image = image;
+    }
+}
+
+unsafe fn set_geometry(conn: &xcb::Connection, root: u32, bar: &mut X11Bar,
+                       direction: &config::Direction, geo: &Geometry) {
+    let values = [(xlib::CWX, geo.x as u32),
+                  (xlib::CWY, geo.y as u32),
+                  (xlib::CWWidth, geo.width as u32),
+                  (xlib::CWHeight, geo.height as u32)
                  ];
-    let _ = xcb::xproto::configure_window(&win.conn, win.win, &values);
+    let _ = xcb::xproto::configure_window(conn, bar.win, &values);
 
-    set_struts(&win.conn, win.win, x as i16, y as i16, width as u16, height as u16);
-    win.width = width;
-    win.height = height;
+    set_struts(conn, bar.win, root, direction, geo.x, geo.y, geo.width, geo.height);
+    bar.x = geo.x as i32;
+    bar.y = geo.y as i32;
+    bar.width = geo.width as u32;
+    bar.height = geo.height as u32;
 }
 
 fn sort_mons(dir: &config::Direction, left: &Monitor, right: &Monitor) -> Ordering {
@@ -272,11 +496,12 @@ fn sort_mons(dir: &config::Direction, left: &Monitor, right: &Monitor) -> Orderi
 fn is_viable_mon(name: &str, pos: &config::Position) -> bool {
     match pos {
         &config::Position::Global(_) => true,
+        &config::Position::All(_) => true,
         &config::Position::Monitor(ref val, _) => val == name,
     }
 }
 
-unsafe fn get_monitors(conn: &xcb::Connection, root: u32) -> Vec<Monitor> {
+unsafe fn get_monitors(conn: &xcb::Connection, root: u32, conf: &config::Config) -> Vec<Monitor> {
     let screen_res_cookie = xcb::randr::get_screen_resources(&conn, root);
     let screen_res_reply = screen_res_cookie.get_reply().unwrap();
     let outputs = screen_res_reply.outputs();
@@ -298,8 +523,15 @@ unsafe fn get_monitors(conn: &xcb::Connection, root: u32) -> Vec<Monitor> {
 
             let crtc = xcb::randr::get_crtc_info(&conn, reply.crtc(), 0).get_reply().unwrap();
 
-            let name = String::from_utf8_lossy(reply.name());
-            let mon = Monitor::from_crtc(name.into(), crtc);
+            let name: String = String::from_utf8_lossy(reply.name()).into();
+            /* The physical size is for the unrotated output, so compare it to
+             * the mode rather than the (maybe rotated) crtc */
+            let (pixels, mm) = match crtc.rotation() as u32 & (xcb::randr::ROTATION_ROTATE_90 | xcb::randr::ROTATION_ROTATE_270) {
+                0 => (crtc.width() as u32, reply.mm_width()),
+                _ => (crtc.height() as u32, reply.mm_width()),
+            };
+            let scale = conf.get_scale(&name, pixels, mm);
+            let mon = Monitor::from_crtc(name, crtc, scale);
 
             ret.push(mon);
         }
@@ -320,15 +552,167 @@ unsafe fn get_monitor<'a>(mons: &'a Vec<Monitor>,
     return viables.into_iter().next();
 }
 
-unsafe fn create_window(size: &config::Size, pos: &config::Position) -> (X11Window, *mut __GLXFBConfigRec) {
-    let (conn, screen_num) = xcb::Connection::connect_with_xlib_display().unwrap();
-    conn.set_event_queue_owner(xcb::EventQueueOwner::Xcb);
+/* Get the monitors that should have a bar on them */
+unsafe fn get_wanted<'a>(mons: &'a Vec<Monitor>,
+                         pos: &config::Position)
+                         -> Vec<&'a Monitor> {
+    match pos {
+        &config::Position::All(_) => mons.iter().collect(),
+        _ => get_monitor(mons, pos).into_iter().collect(),
+    }
+}
 
-    if glx_dec_version(conn.get_raw_dpy()) < 13 {
-        panic!("glx-1.3 is not supported");
+/* Create a bar on mon. Without a monitor the bar is created hidden, and
+ * waits for sync_bars to find it one */
+unsafe fn create_bar(win: &X11Window, mon: Option<&Monitor>,
+                     size: &config::Size, pos: &config::Position) -> X11Bar {
+    let id = win.conn.generate_id();
+    let geo = match mon {
+        Some(x) => Geometry::from_mon_with_conf(x, size, pos.get_direction()),
+        None => Geometry { x: 0, y: 0, width: 1, height: 1 },
+    };
+
+    {
+        let setup = win.conn.get_setup();
+        let screen = setup.roots().nth(win.screen_num as usize).unwrap();
+
+        /* Anything but 0 would show up as an opaque flash before the first draw */
+        let back = if win.transparent { 0 } else { screen.white_pixel() };
+        let cw_values = [
+            (xcb::CW_BACK_PIXEL, back),
+            (xcb::CW_BORDER_PIXEL, screen.black_pixel()),
+            (xcb::CW_EVENT_MASK, xcb::EVENT_MASK_EXPOSURE),
+            (xcb::CW_COLORMAP, win.cmap)
+        ];
+
+        xcb::create_window(&win.conn, win.depth, id, win.root,
+                           geo.x, geo.y, geo.width, geo.height,
+                           0, xcb::WINDOW_CLASS_INPUT_OUTPUT as u16,
+                           win.visual, &cw_values);
+    }
+
+    set_dock(&win.conn, id);
+
+    let title = "ongybar";
+    xcb::change_property(&win.conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            id,
+            xcb::ATOM_WM_NAME,
+            xcb::ATOM_STRING,
+            8, title.as_bytes());
+
+    let protocols = [win.wm_delete_window];
+    xcb::change_property(&win.conn, xcb::PROP_MODE_REPLACE as u8,
+            id, win.wm_protocols, xcb::ATOM_ATOM, 32, &protocols);
+
+    let name = match mon {
+        Some(x) => {
+            set_struts(&win.conn, id, win.root, pos.get_direction(), geo.x, geo.y, geo.width, geo.height);
+            xcb::map_window(&win.conn, id);
+            println!("Created bar on {}: {:?}", x.name, geo);
+            x.name.clone()
+        },
+        None => {
+            println!("No usable monitor yet, the bar stays hidden until one shows up");
+            String::new()
+        },
+    };
+
+    return X11Bar { win: id, mon: name,
+                    x: geo.x as i32, y: geo.y as i32,
+                    width: geo.width as u32, height: geo.height as u32,
+                    mapped: mon.is_some(), root_image: None };
+}
+
+unsafe fn destroy_bar(conn: &xcb::Connection, bar: &X11Bar) {
+    println!("Removing bar from {}", bar.mon);
+    xcb::unmap_window(conn, bar.win);
+    xcb::destroy_window(conn, bar.win);
+}
+
+/* Hide the bar and give back the space it reserved */
+unsafe fn hide_bar(conn: &xcb::Connection, bar: &mut X11Bar) {
+    println!("Monitor {} went away, hiding the bar", bar.mon);
+    xcb::unmap_window(conn, bar.win);
+    clear_struts(conn, bar.win);
+    bar.mapped = false;
+}
+
+/* Make the bars match the current monitor setup. Returns true if anything changed */
+unsafe fn sync_bars(win: &mut X11Window,
+                    pos: &config::Position,
+                    size: &config::Size) -> bool {
+    let mut updated = false;
+    let wanted: Vec<(String, Geometry)> = get_wanted(&win.mons, pos).into_iter()
+        .map(|mon| (mon.name.clone(), Geometry::from_mon_with_conf(mon, size, pos.get_direction())))
+        .collect();
+
+    match pos {
+        &config::Position::All(_) => {
+            /* Bars for monitors that went away are dropped */
+            let (keep, gone): (Vec<X11Bar>, Vec<X11Bar>) = win.bars.drain(..)
+                .partition(|bar| wanted.iter().any(|&(ref name, _)| name == &bar.mon));
+            for bar in gone.iter() {
+                destroy_bar(&win.conn, bar);
+                updated = true;
+            }
+            win.bars = keep;
+        },
+        _ => {
+            /* The one bar we have follows the monitor we chose, and hides
+             * while there is none */
+            let bar = &mut win.bars[0];
+            match wanted.first() {
+                Some(&(ref name, _)) => bar.mon = name.clone(),
+                None => {
+                    if bar.mapped {
+                        hide_bar(&win.conn, bar);
+                        updated = true;
+                    }
+                },
+            }
+        },
     }
 
-    let fbc = get_glxfbconfig(conn.get_raw_dpy(), screen_num, &[
+    for &(ref name, ref geo) in wanted.iter() {
+        let root = win.root;
+        let pos_bar = win.bars.iter().position(|bar| &bar.mon == name);
+        match pos_bar {
+            Some(i) => {
+                let bar = &mut win.bars[i];
+                let current = Geometry { x: bar.x as i16, y: bar.y as i16,
+                                         width: bar.width as u16, height: bar.height as u16 };
+                if &current != geo {
+                    println!("Updating Gemoetry: {:?}", geo);
+                    updated = true;
+                }
+                /* Always reset this, the screen size may have changed,
+                 * which moves the edges our struts are relative to */
+                set_geometry(&win.conn, root, bar, pos.get_direction(), geo);
+
+                if !bar.mapped {
+                    println!("Monitor {} is back, showing the bar", bar.mon);
+                    xcb::map_window(&win.conn, bar.win);
+                    bar.mapped = true;
+                    updated = true;
+                }
+            },
+            None => {
+                let bar = {
+                    let mon = win.mons.iter().find(|mon| &mon.name == name);
+                    create_bar(win, mon, size, pos)
+                };
+                win.bars.push(bar);
+                updated = true;
+            },
+        }
+    }
+
+    return updated;
+}
+
+fn gl_visual_attribs() -> [c_int; 23] {
+    return [
             GLX_X_RENDERABLE    , 1,
             GLX_DRAWABLE_TYPE   , GLX_WINDOW_BIT,
             GLX_RENDER_TYPE     , GLX_RGBA_BIT,
@@ -341,16 +725,90 @@ unsafe fn create_window(size: &config::Size, pos: &config::Position) -> (X11Wind
             GLX_STENCIL_SIZE    , 8,
             GLX_DOUBLEBUFFER    , 1,
             0
-    ]);
+    ];
+}
+
+/* Pick the FBConfig for the OpenGL renderer.
+ * Returns it with the id and depth of its visual, and whether that has alpha */
+unsafe fn choose_gl_visual(conn: &xcb::Connection, screen_num: i32,
+                           conf: &config::Config) -> (GLXFBConfig, u32, u8, bool) {
+    if glx_dec_version(conn.get_raw_dpy()) < 13 {
+        panic!("glx-1.3 is not supported");
+    }
+
+    let visual_attribs = gl_visual_attribs();
+
+    let argb = match conf.background_mode {
+        config::BackgroundMode::Transparent => {
+            let ret = get_argb_glxfbconfig(conn.get_raw_dpy(), screen_num, &visual_attribs);
+            if ret.is_none() {
+                println!("Couldn't find a visual with alpha channel, the bar will be opaque");
+            }
+            ret
+        },
+        _ => None,
+    };
+    let transparent = argb.is_some();
+    let fbc = match argb {
+        Some(x) => x,
+        None => get_glxfbconfig(conn.get_raw_dpy(), screen_num, &visual_attribs),
+    };
 
     let vi: *const xlib::XVisualInfo =
             glXGetVisualFromFBConfig(conn.get_raw_dpy(), fbc);
+    let ret = (fbc, (*vi).visualid as u32, (*vi).depth as u8, transparent);
+    xlib::XFree(vi as *mut c_void);
+
+    return ret;
+}
+
+/* Pick the visual for the software renderer.
+ * Returns its id and depth, and whether it has alpha */
+unsafe fn choose_x_visual(conn: &xcb::Connection, screen_num: i32,
+                          conf: &config::Config) -> (u32, u8, bool) {
+    let setup = conn.get_setup();
+    let screen = setup.roots().nth(screen_num as usize).unwrap();
+
+    if let config::BackgroundMode::Transparent = conf.background_mode {
+        for depth in screen.allowed_depths().filter(|depth| depth.depth() == 32) {
+            for visual in depth.visuals() {
+                if visual.class() as u32 == xcb::VISUAL_CLASS_TRUE_COLOR {
+                    return (visual.visual_id(), 32, true);
+                }
+            }
+        }
+
+        println!("Couldn't find a visual with alpha channel, the bar will be opaque");
+    }
 
-    let dri2_ev = {
+    return (screen.root_visual(), screen.root_depth(), false);
+}
+
+/* Create the window and everything around it. The FBConfig is only chosen
+ * for OpenGL, the software renderer doesn't need one */
+unsafe fn create_window(conf: &config::Config, software: bool)
+                        -> (X11Window, Option<*mut __GLXFBConfigRec>) {
+    let size = &conf.size;
+    let pos = &conf.position;
+
+    let (conn, screen_num) = xcb::Connection::connect_with_xlib_display().unwrap();
+    conn.set_event_queue_owner(xcb::EventQueueOwner::Xcb);
+
+    let (fbc, visual, depth, transparent) = if software {
+        let (visual, depth, transparent) = choose_x_visual(&conn, screen_num, conf);
+        (None, visual, depth, transparent)
+    } else {
+        let (fbc, visual, depth, transparent) = choose_gl_visual(&conn, screen_num, conf);
+        (Some(fbc), visual, depth, transparent)
+    };
+
+    let dri2_ev = if software {
+        None
+    } else {
         conn.prefetch_extension_data(dri2::id());
         match conn.get_extension_data(dri2::id()) {
             None => { panic!("could not load dri2 extension") },
-            Some(r) => { r.first_event() }
+            Some(r) => { Some(r.first_event()) }
         }
     };
 
@@ -370,84 +828,89 @@ unsafe fn create_window(size: &config::Size, pos: &config::Position) -> (X11Wind
     };
 
     let cmap = conn.generate_id();
-    let win = conn.generate_id();
-    let ret_width;
-    let ret_height;
+    let leader = conn.generate_id();
+    let gc = conn.generate_id();
+    let root;
     let mons;
 
     {
         let setup = conn.get_setup();
-        let screen = setup.roots().nth((*vi).screen as usize).unwrap();
+        let screen = setup.roots().nth(screen_num as usize).unwrap();
+        root = screen.root();
 
-        let _ = xcb::randr::select_input(&conn, screen.root(), xcb::randr::NOTIFY_MASK_CRTC_CHANGE as u16).request_check();
+        let _ = xcb::randr::select_input(&conn, root,
+                                         (xcb::randr::NOTIFY_MASK_CRTC_CHANGE |
+                                          xcb::randr::NOTIFY_MASK_OUTPUT_CHANGE) as u16).request_check();
+
+        /* To notice when the wallpaper changes */
+        if let config::BackgroundMode::Root = conf.background_mode {
+            xcb::change_window_attributes(&conn, root,
+                                          &[(xcb::CW_EVENT_MASK, xcb::EVENT_MASK_PROPERTY_CHANGE)]);
+        }
 
         xcb::create_colormap(&conn, xcb::COLORMAP_ALLOC_NONE as u8,
-                cmap, screen.root(), (*vi).visualid as u32);
+                cmap, root, visual);
 
         let cw_values = [
             (xcb::CW_BACK_PIXEL, screen.white_pixel()),
             (xcb::CW_BORDER_PIXEL, screen.black_pixel()),
-            (xcb::CW_EVENT_MASK, xcb::EVENT_MASK_EXPOSURE),
             (xcb::CW_COLORMAP, cmap)
         ];
 
-        mons = get_monitors(&conn, screen.root());
-
-        let mon = match get_monitor(&mons, pos) {
-            Some(x) => x,
-            None => panic!("Couldn't get a usable monitor"),
-        };
-
-        let geo = Geometry::from_mon_with_conf(&mon, &size, pos.get_direction());
-
-        xcb::create_window(&conn, (*vi).depth as u8, win, screen.root(),
-                           geo.x, geo.y, geo.width, geo.height,
+        xcb::create_window(&conn, depth, leader, root,
+                           0, 0, 1, 1,
                            0, xcb::WINDOW_CLASS_INPUT_OUTPUT as u16,
-                           (*vi).visualid as u32, &cw_values);
+                           visual, &cw_values);
+        /* The software renderer puts its frames on the bars with this */
+        xcb::create_gc(&conn, gc, leader, &[]);
 
-        set_struts(&conn, win, geo.x, geo.y, geo.width, geo.height);
-        ret_width = geo.width;
-        ret_height = geo.height;
+        mons = get_monitors(&conn, root, conf);
     }
 
-    xlib::XFree(vi as *mut c_void);
-
-    set_dock(&conn, win);
-
-    let title = "ongybar";
-    xcb::change_property(&conn,
-            xcb::PROP_MODE_REPLACE as u8,
-            win,
-            xcb::ATOM_WM_NAME,
-            xcb::ATOM_STRING,
-            8, title.as_bytes());
-
-    let protocols = [wm_delete_window];
-    xcb::change_property(&conn, xcb::PROP_MODE_REPLACE as u8,
-            win, wm_protocols, xcb::ATOM_ATOM, 32, &protocols);
+    let randr_base = conn.get_extension_data(&mut xcb::randr::id()).unwrap().first_event();
+    let root_pmap_atoms = [
+        xcb::intern_atom(&conn, false, "_XROOTPMAP_ID").get_reply().unwrap().atom(),
+        xcb::intern_atom(&conn, false, "ESETROOT_PMAP_ID").get_reply().unwrap().atom(),
+    ];
 
-    xcb::map_window(&conn, win);
-    conn.flush();
-    xlib::XSync(conn.get_raw_dpy(), xlib::False);
+    let mut win = X11Window { conn: conn, root: root, leader: leader,
+                              visual: visual, depth: depth,
+                              transparent: transparent, gc: gc,
+                              dri2_ev: dri2_ev,
+                              screen_num: screen_num, randr_ev: randr_base,
+                              wm_protocols: wm_protocols, cmap: cmap,
+                              wm_delete_window: wm_delete_window,
+                              mons: mons, bars: Vec::new(),
+                              root_pmap_atoms: root_pmap_atoms, root_serial: 0,
+                              running: true };
+
+    /* Single bars always exist, even if there's no monitor for them yet */
+    let first = match pos {
+        &config::Position::All(_) => None,
+        _ => Some(create_bar(&win, get_monitor(&win.mons, pos), size, pos)),
+    };
+    win.bars.extend(first);
+    sync_bars(&mut win, pos, size);
 
-    let randr_base = conn.get_extension_data(&mut xcb::randr::id()).unwrap().first_event();
+    if let config::BackgroundMode::Root = conf.background_mode {
+        update_root_images(&mut win);
+    }
 
+    win.conn.flush();
+    xlib::XSync(win.conn.get_raw_dpy(), xlib::False);
 
-    let win = X11Window { conn: conn, win: win, dri2_ev: dri2_ev,
-                          screen_num: screen_num, randr_ev: randr_base,
-                          height: ret_height as u32, width: ret_width as u32,
-                          wm_protocols: wm_protocols, cmap: cmap,
-                          wm_delete_window: wm_delete_window, mons: mons };
     return (win, fbc);
 }
 
-unsafe fn make_glcontext(win: &X11Window, fbc: *mut __GLXFBConfigRec) -> *mut __GLXcontextRec {
+/* Create a direct GL 3.0 context for fbc */
+unsafe fn create_context(conn: &xcb::Connection, screen_num: i32,
+                         fbc: GLXFBConfig) -> Result<GLXContext, String> {
     let glx_exts = CStr::from_ptr(
-        glXQueryExtensionsString(win.conn.get_raw_dpy(), win.screen_num))
+        glXQueryExtensionsString(conn.get_raw_dpy(), screen_num))
         .to_str().unwrap();
 
     if !check_glx_extension(&glx_exts, "GLX_ARB_create_context") {
-        panic!("could not find GLX extension GLX_ARB_create_context");
+        return Err(String::from("could not find GLX extension GLX_ARB_create_context"));
     }
 
     // with glx, no need of a current context is needed to load symbols
@@ -460,7 +923,7 @@ unsafe fn make_glcontext(win: &X11Window, fbc: *mut __GLXFBConfigRec) -> *mut __
     gl::load_with(|n| load_gl_func(&n));
 
     if !gl::GenVertexArrays::is_loaded() {
-        panic!("no GL3 support available!");
+        return Err(String::from("no GL3 support available!"));
     }
 
     // installing an event handler to check if error is generated
@@ -472,22 +935,35 @@ unsafe fn make_glcontext(win: &X11Window, fbc: *mut __GLXFBConfigRec) -> *mut __
         GLX_CONTEXT_MINOR_VERSION_ARB as c_int, 0,
         0
     ];
-    let ctx = glx_create_context_attribs(win.conn.get_raw_dpy(), fbc, null_mut(),
+    let ctx = glx_create_context_attribs(conn.get_raw_dpy(), fbc, null_mut(),
             xlib::True, &context_attribs[0] as *const c_int);
 
-    win.conn.flush();
-    xlib::XSync(win.conn.get_raw_dpy(), xlib::False);
+    conn.flush();
+    xlib::XSync(conn.get_raw_dpy(), xlib::False);
     xlib::XSetErrorHandler(std::mem::transmute(old_handler));
 
     if ctx.is_null() || CTX_ERROR_OCCURED {
-        panic!("error when creating gl-3.0 context");
+        return Err(String::from("error when creating gl-3.0 context"));
     }
 
-    if glXIsDirect(win.conn.get_raw_dpy(), ctx) == 0 {
-        panic!("obtained indirect rendering context")
+    if glXIsDirect(conn.get_raw_dpy(), ctx) == 0 {
+        glXDestroyContext(conn.get_raw_dpy(), ctx);
+        return Err(String::from("obtained indirect rendering context"));
     }
 
-    glXMakeCurrent(win.conn.get_raw_dpy(), win.win as xlib::XID, ctx);
+    return Ok(ctx);
+}
+
+unsafe fn make_glcontext(win: &X11Window, fbc: *mut __GLXFBConfigRec) -> *mut __GLXcontextRec {
+    let ctx = match create_context(&win.conn, win.screen_num, fbc) {
+        Ok(x) => x,
+        Err(x) => {
+            println!("Can't use OpenGL: {}", x);
+            std::process::exit(1);
+        },
+    };
+
+    glXMakeCurrent(win.conn.get_raw_dpy(), win.leader as xlib::XID, ctx);
 
     let mut major = [1];
     let mut minor = [1];
@@ -500,65 +976,101 @@ unsafe fn make_glcontext(win: &X11Window, fbc: *mut __GLXFBConfigRec) -> *mut __
     return ctx;
 }
 
-fn update_mons(mons: &mut Vec<Monitor>, name: &String, crtc: xcb::randr::CrtcChange) {
-    let mut found = false;
-    for mon in mons.iter_mut() {
-        if &mon.name == name {
-            found = true;
-            mon.update_crtc(crtc);
-        }
+/* Go through everything the OpenGL renderer needs, without panicking */
+unsafe fn check_gl(conn: &xcb::Connection, screen_num: i32) -> Result<(), String> {
+    let mut error_base: c_int = 0;
+    let mut event_base: c_int = 0;
+    if glXQueryExtension(conn.get_raw_dpy(), &mut error_base as *mut c_int,
+                         &mut event_base as *mut c_int) == 0 {
+        return Err(String::from("the X server doesn't support GLX"));
     }
 
-    if !found {
-        let mon = Monitor::from_change(name.clone(), crtc);
-        mons.push(mon);
+    if glx_dec_version(conn.get_raw_dpy()) < 13 {
+        return Err(String::from("glx-1.3 is not supported"));
+    }
+
+    conn.prefetch_extension_data(dri2::id());
+    if conn.get_extension_data(dri2::id()).is_none() {
+        return Err(String::from("could not load dri2 extension"));
+    }
+
+    let visual_attribs = gl_visual_attribs();
+    let mut fbcount: c_int = 0;
+    let fbcs = glXChooseFBConfig(conn.get_raw_dpy(), screen_num,
+                                 visual_attribs.as_ptr(), &mut fbcount as *mut c_int);
+    if fbcount == 0 {
+        return Err(String::from("could not find compatible fb config"));
+    }
+    let fbc = *fbcs;
+    xlib::XFree(fbcs as *mut c_void);
+
+    match create_context(conn, screen_num, fbc) {
+        Ok(ctx) => {
+            glXDestroyContext(conn.get_raw_dpy(), ctx);
+            return Ok(());
+        },
+        Err(x) => return Err(x),
     }
 }
 
-unsafe fn handle_randr_event(win: &mut X11Window,
-                          ev: xcb::Event<xcb::ffi::xcb_generic_event_t>,
-                          pos: &config::Position,
-                          size: &config::Size) -> bool {
-    let mut updated = false;
-    let v: &xcb::randr::NotifyEvent = xcb::cast_event(&ev);
-    let d = v.u().cc();
-    let crtc = xcb::randr::get_crtc_info(&win.conn, d.crtc(), 0).get_reply().unwrap();
-    for output in crtc.outputs() {
-        let o = xcb::randr::get_output_info(&win.conn, *output, 0).get_reply().unwrap();
-        let name = String::from_utf8_lossy(o.name()).into();
-        update_mons(&mut win.mons, &name, d);
-
-        /* If the update didn't affect a viable monitor, we can ignore it */
-        if !is_viable_mon(name.borrow(), pos) {
-            println!("Don't care about this change!");
-            continue;
+/// Check whether the OpenGL renderer can be used on this display
+pub fn gl_available() -> bool {
+    unsafe {
+        let (conn, screen_num) = match xcb::Connection::connect_with_xlib_display() {
+            Ok(x) => x,
+            Err(_) => return false,
+        };
+
+        match check_gl(&conn, screen_num) {
+            Ok(_) => return true,
+            Err(x) => {
+                println!("Can't use OpenGL: {}", x);
+                return false;
+            },
         }
-        /* A viable monitor changed, so we reset our gemoetry */
+    }
+}
 
-        let geo;
-        {
-            let mon = match get_monitor(&win.mons, pos) {
-                Some(x) => x,
-                None => panic!("The chosen mon dissapeared. Hiding isn't implemented yet")
-            };
-            geo = Geometry::from_mon_with_conf(&mon, size, pos.get_direction());
+unsafe fn handle_randr_event(win: &mut X11Window, conf: &config::Config) -> bool {
+    /* Outputs may have come or gone as well, so just take a fresh look at everything */
+    win.mons = get_monitors(&win.conn, win.root, conf);
+
+    let updated = sync_bars(win, &conf.position, &conf.size);
+    /* Bars may have moved to a different part of the wallpaper */
+    if updated {
+        if let config::BackgroundMode::Root = conf.background_mode {
+            update_root_images(win);
         }
-        println!("Updating Gemoetry: {:?}", geo);
-        set_geometry(win,
-                     geo.x as i32, geo.y as i32,
-                     geo.width as u32, geo.height as u32);
-        updated = true;
     }
 
     return updated;
 }
 
-static mut RUN: bool = true;
+/* Put a frame from the software renderer on the bar */
+unsafe fn put_pixels(win: &X11Window, bar: &X11Bar, data: &[u8]) {
+    let stride = bar.width as usize * 4;
+    if stride == 0 || data.len() < stride * bar.height as usize {
+        return;
+    }
+
+    /* Split the frame, so no request gets larger than the server allows.
+     * The length is in units of 4 bytes, and the header needs some of it */
+    let max = (win.conn.get_maximum_request_length() as usize * 4).saturating_sub(64);
+    let rows = std::cmp::max(max / stride, 1);
+    let mut y = 0;
+    while y < bar.height as usize {
+        let count = std::cmp::min(rows, bar.height as usize - y);
+        xcb::put_image(&win.conn, xcb::IMAGE_FORMAT_Z_PIXMAP as u8, bar.win, win.gc,
+                       bar.width as u16, count as u16, 0, y as i16, 0, win.depth,
+                       &data[y * stride..(y + count) * stride]);
+        y += count;
+    }
+    win.conn.flush();
+}
 
 unsafe fn handle_event(win: &mut X11Window,
                           ev: xcb::Event<xcb::ffi::xcb_generic_event_t>,
-                          pos: &config::Position,
-                          size: &config::Size) -> bool {
+                          conf: &config::Config) -> bool {
 
     let ev_type = ev.response_type() & !0x80;
     let ret = match ev_type {
@@ -566,15 +1078,24 @@ unsafe fn handle_event(win: &mut X11Window,
             true
         },
         xcb::KEY_PRESS => {
-            RUN = false;
+            win.running = false;
             false
         },
+        xcb::PROPERTY_NOTIFY => {
+            let pev = xcb::cast_event::<xcb::PropertyNotifyEvent>(&ev);
+            if pev.window() == win.root && win.root_pmap_atoms.contains(&pev.atom()) {
+                update_root_images(win);
+                true
+            } else {
+                false
+            }
+        },
         xcb::CLIENT_MESSAGE => {
             let cmev = xcb::cast_event::<xcb::ClientMessageEvent>(&ev);
             if cmev.type_() == win.wm_protocols && cmev.format() == 32 {
                 let protocol = cmev.data().data32()[0];
                 if protocol == win.wm_delete_window {
-                    RUN = false;
+                    win.running = false;
                     println!("Window got deleted. Stopping");
                 }
             }
@@ -583,7 +1104,11 @@ unsafe fn handle_event(win: &mut X11Window,
         _ => {
             // following stuff is not obvious at all, but is necessary
             // to handle GL when XCB owns the event queue
-            if ev_type == win.dri2_ev || ev_type == win.dri2_ev+1 {
+            let is_dri2 = match win.dri2_ev {
+                Some(x) => ev_type == x || ev_type == x + 1,
+                None => false,
+            };
+            if is_dri2 {
                 // these are libgl dri2 event that need special handling
                 // see https://bugs.freedesktop.org/show_bug.cgi?id=35945#c4
                 // and mailing thread starting here:
@@ -605,7 +1130,7 @@ unsafe fn handle_event(win: &mut X11Window,
                 }
                 true
             } else if ev_type == win.randr_ev + xcb::randr::NOTIFY {
-                handle_randr_event(win, ev, pos, size)
+                handle_randr_event(win, conf)
             } else {
                 println!("Got an unkown event!: {}", ev_type);
                 false
@@ -617,85 +1142,99 @@ unsafe fn handle_event(win: &mut X11Window,
     return ret;
 }
 
-unsafe fn poll_event(win: &mut X11Window,
-                        pos: &config::Position,
-                        size: &config::Size) {
-    if let Some(ev) = win.conn.poll_for_event() {
-        handle_event(win, ev, pos, size);
-    }
+/// The bars as X windows, drawn with OpenGL or put there by the software renderer
+pub struct X11Backend<'a, V> {
+    win: X11Window,
+    /* Null when rendering in software */
+    ctx: GLXContext,
+    graphics: V,
+    conf: &'a config::Config,
 }
 
-unsafe fn wait_event(win: &mut X11Window,
-                        pos: &config::Position,
-                        size: &config::Size) -> bool {
-    if let Some(ev) = win.conn.poll_for_event() {
-        return handle_event(win, ev, pos, size);
-    }
+impl<'a, V> X11Backend<'a, V>
+    where V: Surface {
+    /// Connect to the X server and set up the bars.
+    /// create is called once there is a GL context, unless software is set
+    pub fn new<L>(conf: &'a config::Config, create: L, software: bool) -> Self
+        where L: FnOnce() -> V {
+        unsafe {
+            let (win, fbc) = create_window(conf, software);
+            let ctx = match fbc {
+                Some(x) => make_glcontext(&win, x),
+                None => null_mut(),
+            };
 
-    return false;
+            return X11Backend { win: win, ctx: ctx, graphics: create(), conf: conf };
+        }
+    }
 }
 
-pub fn do_x11main<F, G, L, V>(mut draw_window: F, create: L, fun_list: G,
-                              size: config::Size, position: config::Position)
-    where F: FnMut(&mut V, u32, u32),
-          L: FnOnce() -> V,
-          G: std::iter::IntoIterator<Item=(c_int, Box<FnMut() -> bool>)> {
-    unsafe {
-        let (win, fbc) = create_window(&size, &position);
-        let ctx = make_glcontext(&win, fbc);
-        let mut graphics = create();
-        let xcb_fd: c_int = xcb::ffi::base::xcb_get_file_descriptor(win.conn.get_raw_conn());
-        let win_cell = RefCell::new(win);
-
-        let xcbt: Token = Token(xcb_fd as usize);
-        let poll = Poll::new().unwrap();
-
-        poll.register(&mio::unix::EventedFd(&xcb_fd),
-                      xcbt, Ready::readable(),
-                      PollOpt::level()).unwrap();
-
-        let mut map = HashMap::new();
-        map.insert(xcbt, Box::new(||  {
-            wait_event(win_cell.borrow_mut().deref_mut(),
-                       &position, &size)
-        }) as Box<FnMut() -> bool>);
-
-        for x in fun_list {
-            let tok = Token(x.0 as usize);
-            poll.register(&mio::unix::EventedFd(&x.0), tok, Ready::readable(),
-                          PollOpt::level()).unwrap();
-            map.insert(tok, x.1);
+impl<'a, V> Backend for X11Backend<'a, V>
+    where V: Surface {
+    type Surface = V;
+
+    fn get_fds(&self) -> Vec<c_int> {
+        unsafe {
+            return vec![xcb::ffi::base::xcb_get_file_descriptor(self.win.conn.get_raw_conn())];
         }
+    }
 
+    fn handle_events(&mut self) -> bool {
+        let mut redraw = false;
+        while let Some(ev) = self.win.conn.poll_for_event() {
+            unsafe {
+                redraw |= handle_event(&mut self.win, ev, self.conf);
+            }
+        }
 
-        let mut events = Events::with_capacity(map.len() + 2);
-        RUN = true;
+        return redraw;
+    }
 
-        loop {
-            poll_event(win_cell.borrow_mut().deref_mut(),
-                       &position, &size);
-            poll.poll(&mut events, None).unwrap();
+    fn is_running(&self) -> bool {
+        return self.win.running;
+    }
 
-            for event in events.iter() {
-                let mut fun = map.get_mut(&event.token()).unwrap();
-                if fun.deref_mut()() {
-                    let win = win_cell.borrow();
-                    draw_window(&mut graphics, win.width, win.height);
-                    glXSwapBuffers(win.conn.get_raw_dpy(), win.win as xlib::XID);
-                }
-            }
+    fn present<F>(&mut self, draw: &mut F)
+        where F: FnMut(&mut V, &Frame) {
+        let win = &self.win;
+        for bar in win.bars.iter().filter(|bar| bar.mapped) {
+            let scale = match win.mons.iter().find(|mon| mon.name == bar.mon) {
+                Some(x) => x.scale,
+                None => 1.0,
+            };
 
-            if !RUN {
-                break;
+            unsafe {
+                if !self.ctx.is_null() {
+                    glXMakeCurrent(win.conn.get_raw_dpy(), bar.win as xlib::XID, self.ctx);
+                }
+                self.graphics.begin(bar.width, bar.height);
+                draw(&mut self.graphics, &Frame { width: bar.width, height: bar.height,
+                                                  scale: scale, root: bar.root_image.as_ref() });
+                match self.graphics.pixels() {
+                    Some(x) => put_pixels(win, bar, x),
+                    None => glXSwapBuffers(win.conn.get_raw_dpy(), bar.win as xlib::XID),
+                }
             }
         }
+    }
+}
 
-        let win = win_cell.borrow();
-        glXDestroyContext(win.conn.get_raw_dpy(), ctx);
+impl<'a, V> Drop for X11Backend<'a, V> {
+    fn drop(&mut self) {
+        let win = &self.win;
+        unsafe {
+            if !self.ctx.is_null() {
+                glXMakeCurrent(win.conn.get_raw_dpy(), 0, null_mut());
+                glXDestroyContext(win.conn.get_raw_dpy(), self.ctx);
+            }
 
-        xcb::unmap_window(&win.conn, win.win);
-        xcb::destroy_window(&win.conn, win.win);
-        xcb::free_colormap(&win.conn, win.cmap);
-        win.conn.flush();
+            for bar in win.bars.iter() {
+                destroy_bar(&win.conn, bar);
+            }
+            xcb::free_gc(&win.conn, win.gc);
+            xcb::destroy_window(&win.conn, win.leader);
+            xcb::free_colormap(&win.conn, win.cmap);
+            win.conn.flush();
+        }
     }
 }